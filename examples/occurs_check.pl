@@ -0,0 +1,8 @@
+% `X = f(X)` finishes with every value of the `occurs_check` flag.
+% loading this file should print no warnings.
+:- set_prolog_flag(occurs_check, false), X = f(X), X = f(f(X)).
+:- set_prolog_flag(occurs_check, true), \+ X = f(X).
+:- set_prolog_flag(occurs_check, error), catch(X = f(X), error(occurs_check(_, _), _), true).
+:- set_prolog_flag(occurs_check, false).
+
+cyclic(X) :- X = f(X).
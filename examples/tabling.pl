@@ -0,0 +1,27 @@
+% tabled predicates terminate on left and mutual recursion.
+% loading this file should print no warnings.
+
+% left recursion over a graph with a cycle
+:- table path/2.
+edge(a, b). edge(b, c). edge(c, a). edge(c, d).
+path(X, Y) :- path(X, Z), edge(Z, Y).
+path(X, Y) :- edge(X, Y).
+
+:- setof(Y, path(a, Y), [a, b, c, d]).
+:- \+ path(d, _).
+
+% mutual recursion between two tabled predicates
+:- table even/1, odd/1.
+even(0).
+even(N) :- odd(M), M < 10, N is M + 1.
+odd(N) :- even(M), M < 10, N is M + 1.
+
+:- setof(N, odd(N), [1, 3, 5, 7, 9]).
+:- even(10), \+ odd(11).
+
+% keeping only the shortest distance of each path
+:- table dist(_, _, min).
+dist(X, Y, 1) :- edge(X, Y).
+dist(X, Y, D) :- dist(X, Z, D0), edge(Z, Y), D is D0 + 1.
+
+:- dist(a, d, 3), dist(a, a, 3).
@@ -0,0 +1,375 @@
+use crate::{
+    error::Loc,
+    expr::{Expr, CONS, NIL},
+    unify::ApplyError,
+};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+
+#[derive(Clone, Debug)]
+pub(crate) enum Number {
+    Int(i64),
+    // only used for integers that do not fit in an `i64`
+    Big(BigInt),
+    Float(f64),
+}
+
+use Number::*;
+
+// the most bits an integer made by `<<` or `^` can have,
+// so that a huge result is an error instead of running out of memory
+const MAX_BITS: u64 = 1 << 20;
+
+// integers go back to being machine words as soon as they fit in one
+impl From<BigInt> for Number {
+    fn from(x: BigInt) -> Self {
+        match x.to_i64() {
+            Some(x) => Int(x),
+            None => Big(x),
+        }
+    }
+}
+
+impl Number {
+    pub(crate) fn into_expr(self, loc: Loc) -> Expr {
+        match self {
+            Int(val) => Expr::Int { val, loc },
+            Big(val) => Expr::BigInt { val, loc },
+            Float(val) => Expr::Float { val, loc },
+        }
+    }
+
+    // compares two numbers by value, so `1 =:= 1.0`.
+    // gives `None` if one of them is not a number (NaN).
+    pub(crate) fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Int(x), Int(y)) => Some(x.cmp(y)),
+            (Float(_), _) | (_, Float(_)) => self.to_float().partial_cmp(&other.to_float()),
+            (x, y) => Some(x.to_big().ok()?.cmp(&y.to_big().ok()?)),
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Float(_))
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Int(x) => *x == 0,
+            Big(_) => false,
+            Float(x) => *x == 0.0,
+        }
+    }
+
+    fn to_float(&self) -> f64 {
+        match self {
+            Int(x) => *x as f64,
+            Big(x) => x
+                .to_f64()
+                .unwrap_or(f64::INFINITY.copysign(x.sign() as i8 as f64)),
+            Float(x) => *x,
+        }
+    }
+
+    fn to_big(&self) -> Result<BigInt, ApplyError> {
+        match self {
+            Int(x) => Ok(BigInt::from(*x)),
+            Big(x) => Ok(x.clone()),
+            Float(val) => Err(ApplyError::Type(
+                "integer",
+                Expr::Float {
+                    val: *val,
+                    loc: Loc::default(),
+                },
+            )),
+        }
+    }
+}
+
+// evaluates an arithmetic expression, as done by the right hand side of `is/2`
+pub(crate) fn eval(e: &Expr) -> Result<Number, ApplyError> {
+    crate::with_stacker(|| match e {
+        Expr::Int { val, .. } => Ok(Int(*val)),
+        Expr::BigInt { val, .. } => Ok(Big(val.clone())),
+        Expr::Float { val, .. } => Ok(Float(*val)),
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Str { .. } => Err(ApplyError::Type("evaluable", e.clone())),
+        // a one-element list evaluates to its element, so `X is "a"` works with codes
+        Expr::Fun { name, args, .. }
+            if name == CONS && args.len() == 2 && args[1] == Expr::atom(NIL, Loc::default()) =>
+        {
+            eval(&args[0])
+        }
+        Expr::Fun { name, args, loc } => {
+            let vals = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            apply(name, &vals).unwrap_or_else(|| {
+                let culprit = Expr::Fun {
+                    name: "/".to_string(),
+                    args: vec![
+                        Expr::Fun {
+                            name: name.clone(),
+                            args: Vec::new(),
+                            loc: *loc,
+                        },
+                        Expr::Int {
+                            val: args.len() as i64,
+                            loc: *loc,
+                        },
+                    ],
+                    loc: *loc,
+                };
+                Err(ApplyError::Type("evaluable", culprit))
+            })
+        }
+    })
+}
+
+// applies an evaluable functor to its (already evaluated) arguments.
+// integers stay integers as long as possible, anything involving a float is a float.
+// returns `None` if there is no such functor.
+fn apply(name: &str, vals: &[Number]) -> Option<Result<Number, ApplyError>> {
+    Some(match (name, vals) {
+        ("pi", []) => Ok(Float(std::f64::consts::PI)),
+        ("e", []) => Ok(Float(std::f64::consts::E)),
+        ("inf", []) => Ok(Float(f64::INFINITY)),
+        ("nan", []) => Ok(Float(f64::NAN)),
+        ("epsilon", []) => Ok(Float(f64::EPSILON)),
+
+        ("+", [x]) => Ok(x.clone()),
+        ("-", [Float(x)]) => Ok(Float(-x)),
+        ("abs", [Float(x)]) => Ok(Float(x.abs())),
+        ("sign", [Float(x)]) => Ok(Float(if *x == 0.0 { 0.0 } else { x.signum() })),
+        ("min", [x, y]) => Ok(if y.compare(x) == Some(Ordering::Less) {
+            y
+        } else {
+            x
+        }
+        .clone()),
+        ("max", [x, y]) => Ok(if y.compare(x) == Some(Ordering::Greater) {
+            y
+        } else {
+            x
+        }
+        .clone()),
+
+        ("+", [x, y]) if x.is_float() || y.is_float() => float(x.to_float() + y.to_float()),
+        ("-", [x, y]) if x.is_float() || y.is_float() => float(x.to_float() - y.to_float()),
+        ("*", [x, y]) if x.is_float() || y.is_float() => float(x.to_float() * y.to_float()),
+        // dividing integers only gives a float when the division is not exact
+        ("/", [_, y]) if y.is_zero() => Err(ApplyError::Evaluation("zero_divisor")),
+        ("/", [x, y]) if !x.is_float() && !y.is_float() => match int_op("rem", x, y)? {
+            Ok(r) if r.is_zero() => int_op("//", x, y)?,
+            _ => float(x.to_float() / y.to_float()),
+        },
+        ("/", [x, y]) => float(x.to_float() / y.to_float()),
+        ("^", [x, y]) if x.is_float() || y.is_float() => float(x.to_float().powf(y.to_float())),
+        ("**", [x, y]) => float(x.to_float().powf(y.to_float())),
+
+        ("sqrt", [x]) => float(x.to_float().sqrt()),
+        ("exp", [x]) => float(x.to_float().exp()),
+        ("log", [x]) if x.to_float() <= 0.0 => Err(ApplyError::Evaluation("undefined")),
+        ("log", [x]) => float(x.to_float().ln()),
+        ("log2", [x]) if x.to_float() <= 0.0 => Err(ApplyError::Evaluation("undefined")),
+        ("log2", [x]) => float(x.to_float().log2()),
+        ("log", [b, x]) if b.to_float() <= 0.0 || x.to_float() <= 0.0 => {
+            Err(ApplyError::Evaluation("undefined"))
+        }
+        ("log", [b, x]) => float(x.to_float().ln() / b.to_float().ln()),
+        ("sin", [x]) => float(x.to_float().sin()),
+        ("cos", [x]) => float(x.to_float().cos()),
+        ("tan", [x]) => float(x.to_float().tan()),
+        ("asin", [x]) => float(x.to_float().asin()),
+        ("acos", [x]) => float(x.to_float().acos()),
+        ("atan", [x]) => float(x.to_float().atan()),
+        ("atan" | "atan2", [y, x]) => float(y.to_float().atan2(x.to_float())),
+        ("sinh", [x]) => float(x.to_float().sinh()),
+        ("cosh", [x]) => float(x.to_float().cosh()),
+        ("tanh", [x]) => float(x.to_float().tanh()),
+        ("copysign", [x, y]) => float(x.to_float().copysign(y.to_float())),
+
+        ("float", [x]) => float(x.to_float()),
+        ("float_integer_part", [x]) => float(x.to_float().trunc()),
+        ("float_fractional_part", [x]) => float(x.to_float().fract()),
+        ("integer" | "round", [x]) => to_int(x, f64::round),
+        ("truncate", [x]) => to_int(x, f64::trunc),
+        ("ceiling", [x]) => to_int(x, f64::ceil),
+        ("floor", [x]) => to_int(x, f64::floor),
+
+        // the rest only works on integers
+        (_, [x]) => unary_int_op(name, x)?,
+        (_, [x, y]) => int_op(name, x, y)?,
+        _ => return None,
+    })
+}
+
+fn unary_int_op(name: &str, x: &Number) -> Option<Result<Number, ApplyError>> {
+    if !["-", "abs", "sign", "\\", "msb"].contains(&name) {
+        return None;
+    }
+    let small = match (name, x) {
+        ("-", Int(x)) => x.checked_neg(),
+        ("abs", Int(x)) => x.checked_abs(),
+        ("sign", Int(x)) => Some(x.signum()),
+        ("\\", Int(x)) => Some(!x),
+        _ => None,
+    };
+    if let Some(r) = small {
+        return Some(Ok(Int(r)));
+    }
+    Some(x.to_big().and_then(|x| {
+        Ok(match name {
+            "-" => Number::from(-x),
+            "abs" => Number::from(x.abs()),
+            "sign" => Number::from(x.signum()),
+            "\\" => Number::from(!x),
+            _ if x.is_positive() => Int(x.bits() as i64 - 1),
+            _ => return Err(ApplyError::Evaluation("undefined")),
+        })
+    }))
+}
+
+// integer operations are done on machine words,
+// unless the arguments or the result do not fit in them.
+fn int_op(name: &str, x: &Number, y: &Number) -> Option<Result<Number, ApplyError>> {
+    const OPS: [&str; 14] = [
+        "+", "-", "*", "//", "rem", "div", "mod", "gcd", "^", ">>", "<<", "/\\", "\\/", "xor",
+    ];
+    if !OPS.contains(&name) {
+        return None;
+    }
+    if let (Int(x), Int(y)) = (x, y) {
+        if let Some(r) = small_int_op(name, *x, *y) {
+            return Some(r);
+        }
+    }
+    Some(x.to_big().and_then(|x| big_int_op(name, x, y.to_big()?)))
+}
+
+// gives `None` if the result does not fit in an `i64`
+fn small_int_op(name: &str, x: i64, y: i64) -> Option<Result<Number, ApplyError>> {
+    let r = match name {
+        "//" | "rem" | "mod" | "div" if y == 0 => {
+            return Some(Err(ApplyError::Evaluation("zero_divisor")))
+        }
+        "+" => x.checked_add(y),
+        "-" => x.checked_sub(y),
+        "*" => x.checked_mul(y),
+        // `//` and `rem` round towards zero, `div` and `mod` towards negative infinity
+        "//" => x.checked_div(y),
+        "rem" => x.checked_rem(y),
+        "div" => x.checked_sub(modulo(x, y)).and_then(|x| x.checked_div(y)),
+        "mod" => Some(modulo(x, y)),
+        "gcd" => gcd(x, y),
+        "^" => u32::try_from(y).ok().and_then(|y| x.checked_pow(y)),
+        ">>" if (0..64).contains(&y) => Some(x >> y),
+        "<<" => x
+            .checked_shl(y as u32)
+            .filter(|r| (0..64).contains(&y) && r >> y == x),
+        "/\\" => Some(x & y),
+        "\\/" => Some(x | y),
+        "xor" => Some(x ^ y),
+        _ => None,
+    };
+    r.map(|r| Ok(Int(r)))
+}
+
+fn big_int_op(name: &str, x: BigInt, y: BigInt) -> Result<Number, ApplyError> {
+    Ok(Number::from(match name {
+        "//" | "rem" | "mod" | "div" if y.is_zero() => {
+            return Err(ApplyError::Evaluation("zero_divisor"))
+        }
+        "+" => x + y,
+        "-" => x - y,
+        "*" => x * y,
+        "//" => x / y,
+        "rem" => x % y,
+        "div" => x.div_floor(&y),
+        "mod" => x.mod_floor(&y),
+        "gcd" => x.gcd(&y),
+        "^" => return pow(x, y),
+        // shifting by a negative amount shifts the other way
+        ">>" | "<<" => {
+            let left = (name == "<<") != y.is_negative();
+            match y.abs().to_usize() {
+                _ if left && x.is_zero() => x,
+                Some(n) if left && x.bits() + n as u64 <= MAX_BITS => x << n,
+                _ if left => return Err(ApplyError::Evaluation("int_overflow")),
+                Some(n) => x >> n,
+                // everything is shifted out, except for the sign
+                None => BigInt::from(-(x.is_negative() as i64)),
+            }
+        }
+        "/\\" => x & y,
+        "\\/" => x | y,
+        "xor" => x ^ y,
+        _ => unreachable!(),
+    }))
+}
+
+// operations on ordinary numbers should not give infinities or NaNs
+fn float(x: f64) -> Result<Number, ApplyError> {
+    if x.is_nan() {
+        Err(ApplyError::Evaluation("undefined"))
+    } else if x.is_infinite() {
+        Err(ApplyError::Evaluation("float_overflow"))
+    } else {
+        Ok(Float(x))
+    }
+}
+
+// rounds a float to an integer in the way given by `f`
+fn to_int(x: &Number, f: fn(f64) -> f64) -> Result<Number, ApplyError> {
+    match x {
+        Float(x) => BigInt::from_f64(f(*x))
+            .map(Number::from)
+            .ok_or(ApplyError::Evaluation("int_overflow")),
+        x => Ok(x.clone()),
+    }
+}
+
+// remainder that takes the sign of the divisor
+fn modulo(x: i64, y: i64) -> i64 {
+    let m = x.wrapping_rem(y);
+    if m != 0 && (m < 0) != (y < 0) {
+        m + y
+    } else {
+        m
+    }
+}
+
+fn gcd(mut x: i64, mut y: i64) -> Option<i64> {
+    while y != 0 {
+        (x, y) = (y, x.checked_rem(y)?);
+    }
+    x.checked_abs()
+}
+
+// integer exponentiation. negative exponents only make sense for 1 and -1,
+// everything else would need a float.
+fn pow(x: BigInt, y: BigInt) -> Result<Number, ApplyError> {
+    if !y.is_negative() {
+        // 0, 1 and -1 stay small whatever the exponent
+        match x.to_i64() {
+            Some(0) if !y.is_zero() => return Ok(Int(0)),
+            Some(0 | 1) => return Ok(Int(1)),
+            Some(-1) => return Ok(Int(if y.is_odd() { -1 } else { 1 })),
+            _ => {}
+        }
+        let y = y
+            .to_u32()
+            .filter(|y| (x.bits() - 1) * *y as u64 <= MAX_BITS)
+            .ok_or(ApplyError::Evaluation("int_overflow"))?;
+        return Ok(Number::from(x.pow(y)));
+    }
+    match x.to_i64() {
+        Some(1) => Ok(Int(1)),
+        Some(-1) => Ok(Int(if y.is_even() { 1 } else { -1 })),
+        Some(0) => Err(ApplyError::Evaluation("zero_divisor")),
+        _ => Err(ApplyError::Type(
+            "float",
+            Number::from(x).into_expr(Loc::default()),
+        )),
+    }
+}
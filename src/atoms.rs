@@ -0,0 +1,239 @@
+use crate::{
+    builtins::{goal, unify, Subs},
+    error::Loc,
+    expr::{Expr, NIL},
+    flags::OccursCheck,
+    parser::parse_number,
+    terms::list,
+    unify::ApplyError,
+};
+
+// runs the predicates that work on the text of atoms.
+// returns `None` for any other goal.
+pub(crate) fn call(e: &Expr, occurs: OccursCheck) -> Option<Result<Subs, ApplyError>> {
+    let (name, args) = match e {
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        _ => return None,
+    };
+    let loc = e.loc();
+    let unify_goal = |args| unify(e, &goal(name, args, e), occurs);
+    Some(match (name, args) {
+        ("atom_codes" | "atom_chars", [a, l]) => {
+            atom_list(a, l, name == "atom_chars").and_then(|(a, l)| unify_goal(vec![a, l]))
+        }
+        ("char_code", [c, n]) => char_code(c, n).and_then(|(c, n)| unify_goal(vec![c, n])),
+        ("atom_length", [a, l]) => atom_length(a, l).and_then(|len| unify(l, &len, occurs)),
+        ("atom_concat", [a, b, c]) => atom_concat(e, a, b, c, occurs),
+        ("sub_atom", [a, b, l, after, sub]) => sub_atom(e, a, b, l, after, sub, occurs),
+        ("upcase_atom", [a, u]) => {
+            atom_text(a).and_then(|a| unify(u, &Expr::atom(&a.to_uppercase(), loc), occurs))
+        }
+        ("number_codes", [n, l]) => number_codes(n, l).and_then(|(n, l)| unify_goal(vec![n, l])),
+        ("atom_number", [a, n]) => match a {
+            Expr::Var { .. } => match n {
+                Expr::Var { .. } => Err(ApplyError::Instantiation),
+                Expr::Int { .. } | Expr::BigInt { .. } | Expr::Float { .. } => {
+                    unify(a, &Expr::atom(&n.to_string(), loc), occurs)
+                }
+                _ => Err(ApplyError::Type("number", n.clone())),
+            },
+            // an atom that is not a number just fails
+            _ => atom_text(a).and_then(|a| match parse_number(a) {
+                Some(num) => unify(n, &num, occurs),
+                None => Ok(Vec::new()),
+            }),
+        },
+        _ => return None,
+    })
+}
+
+// `atom_codes/2` and `atom_chars/2`: the atom and the list of its codes or characters,
+// where the one that is not given is made from the other
+fn atom_list(a: &Expr, l: &Expr, chars: bool) -> Result<(Expr, Expr), ApplyError> {
+    match a {
+        Expr::Var { .. } => Ok((Expr::atom(&from_list(l, chars)?, a.loc()), l.clone())),
+        _ => Ok((a.clone(), to_list(atom_text(a)?, chars, a.loc()))),
+    }
+}
+
+fn char_code(c: &Expr, n: &Expr) -> Result<(Expr, Expr), ApplyError> {
+    let loc = c.loc();
+    match (c, n) {
+        (Expr::Var { .. }, Expr::Var { .. }) => Err(ApplyError::Instantiation),
+        (Expr::Var { .. }, _) => Ok((Expr::atom(&code(n)?.to_string(), loc), n.clone())),
+        _ => {
+            let val = character(c)? as i64;
+            Ok((c.clone(), Expr::Int { val, loc }))
+        }
+    }
+}
+
+fn atom_length(a: &Expr, l: &Expr) -> Result<Expr, ApplyError> {
+    let len = atom_text(a)?.chars().count();
+    if int(l)?.is_some_and(|l| l < 0) {
+        return Err(ApplyError::Domain("not_less_than_zero", l.clone()));
+    }
+    Ok(Expr::Int {
+        val: len as i64,
+        loc: a.loc(),
+    })
+}
+
+// `atom_concat(A, B, C)`: `C` is `A` followed by `B`.
+// if `A` or `B` is not given, every way of splitting `C` is an answer.
+fn atom_concat(
+    e: &Expr,
+    a: &Expr,
+    b: &Expr,
+    c: &Expr,
+    occurs: OccursCheck,
+) -> Result<Subs, ApplyError> {
+    let loc = e.loc();
+    for part in [a, b] {
+        if !matches!(part, Expr::Var { .. }) {
+            atom_text(part)?;
+        }
+    }
+    if let (Expr::Fun { name: a, .. }, Expr::Fun { name: b, .. }) = (a, b) {
+        return unify(c, &Expr::atom(&format!("{}{}", a, b), loc), occurs);
+    }
+    let whole = atom_text(c)?;
+    let splits = whole.char_indices().map(|(i, _)| i).chain([whole.len()]);
+    let mut subs = Vec::new();
+    for i in splits {
+        let (a, b) = whole.split_at(i);
+        let args = vec![Expr::atom(a, loc), Expr::atom(b, loc), c.clone()];
+        subs.extend(unify(e, &goal("atom_concat", args, e), occurs)?);
+    }
+    Ok(subs)
+}
+
+// `sub_atom(Atom, Before, Length, After, Sub)`: `Sub` is the part of `Atom` that starts
+// after `Before` characters, is `Length` characters long and leaves `After` characters.
+// every part that fits what is given is an answer.
+fn sub_atom(
+    e: &Expr,
+    a: &Expr,
+    b: &Expr,
+    l: &Expr,
+    after: &Expr,
+    sub: &Expr,
+    occurs: OccursCheck,
+) -> Result<Subs, ApplyError> {
+    let loc = e.loc();
+    let text: Vec<char> = atom_text(a)?.chars().collect();
+    let (b, l, after) = (int(b)?, int(l)?, int(after)?);
+    let sub = match sub {
+        Expr::Var { .. } => None,
+        _ => Some(atom_text(sub)?.chars().collect::<Vec<_>>()),
+    };
+    let fits = |n: Option<i64>, val: usize| n.is_none_or(|n| n == val as i64);
+
+    let n = text.len();
+    let mut subs = Vec::new();
+    for start in 0..=n {
+        for len in 0..=n - start {
+            let part = &text[start..start + len];
+            if !fits(b, start)
+                || !fits(l, len)
+                || !fits(after, n - start - len)
+                || sub.as_ref().is_some_and(|sub| sub != part)
+            {
+                continue;
+            }
+            let int = |val: usize| Expr::Int {
+                val: val as i64,
+                loc,
+            };
+            let args = vec![
+                a.clone(),
+                int(start),
+                int(len),
+                int(n - start - len),
+                Expr::atom(&part.iter().collect::<String>(), loc),
+            ];
+            subs.extend(unify(e, &goal("sub_atom", args, e), occurs)?);
+        }
+    }
+    Ok(subs)
+}
+
+// `number_codes(N, L)`: the list is read as a number if it is complete,
+// otherwise it is made from the number
+fn number_codes(n: &Expr, l: &Expr) -> Result<(Expr, Expr), ApplyError> {
+    match n {
+        Expr::Var { .. } | Expr::Int { .. } | Expr::BigInt { .. } | Expr::Float { .. } => {}
+        _ => return Err(ApplyError::Type("number", n.clone())),
+    }
+    match from_list(l, false) {
+        Ok(text) => match parse_number(&text) {
+            Some(num) => Ok((num, l.clone())),
+            None => Err(ApplyError::Syntax("illegal_number")),
+        },
+        Err(err) if matches!(n, Expr::Var { .. }) => Err(err),
+        Err(_) => Ok((n.clone(), to_list(&n.to_string(), false, n.loc()))),
+    }
+}
+
+// the name of an atom
+fn atom_text(e: &Expr) -> Result<&str, ApplyError> {
+    match e {
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Fun { name, args, .. } if args.is_empty() => Ok(name),
+        _ => Err(ApplyError::Type("atom", e.clone())),
+    }
+}
+
+// the integer `e` is, or `None` if it is still a variable
+fn int(e: &Expr) -> Result<Option<i64>, ApplyError> {
+    match e {
+        Expr::Var { .. } => Ok(None),
+        Expr::Int { val, .. } => Ok(Some(*val)),
+        _ => Err(ApplyError::Type("integer", e.clone())),
+    }
+}
+
+// a one-character atom, like `a`
+fn character(e: &Expr) -> Result<char, ApplyError> {
+    let name = atom_text(e).map_err(|err| match err {
+        ApplyError::Type(..) => ApplyError::Type("character", e.clone()),
+        err => err,
+    })?;
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(ApplyError::Type("character", e.clone())),
+    }
+}
+
+// a character code, like `97`
+fn code(e: &Expr) -> Result<char, ApplyError> {
+    match e {
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Int { val, .. } => u32::try_from(*val)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(ApplyError::Representation("character_code")),
+        _ => Err(ApplyError::Type("integer", e.clone())),
+    }
+}
+
+// the text of a list of character codes, or of characters if `chars` is set
+fn from_list(l: &Expr, chars: bool) -> Result<String, ApplyError> {
+    list(l)?
+        .into_iter()
+        .map(|e| if chars { character(e) } else { code(e) })
+        .collect()
+}
+
+// the list of the character codes of `text`, or of its characters if `chars` is set
+fn to_list(text: &str, chars: bool, loc: Loc) -> Expr {
+    let items = text
+        .chars()
+        .map(|c| match chars {
+            true => Expr::atom(&c.to_string(), loc),
+            false => Expr::Int { val: c as i64, loc },
+        })
+        .collect();
+    Expr::list(items, Expr::atom(NIL, loc))
+}
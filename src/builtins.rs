@@ -0,0 +1,138 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    arith,
+    expr::{Expr, CONS, NIL},
+    flags::OccursCheck,
+    unify::{unify_exprs, ApplyError},
+    Context,
+};
+
+// every substitution is one way of making the goal true
+pub(crate) type Subs = Vec<HashMap<u64, Expr>>;
+
+// runs a goal natively if it is a built-in predicate.
+// returns `None` if it is not one, in which case it has to be looked up in the rules.
+pub(crate) fn call(e: &Expr, ctx: &Context) -> Option<Result<Subs, ApplyError>> {
+    let (name, args) = match e {
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        _ => return None,
+    };
+    let occurs = ctx.flags.occurs_check.get();
+    Some(match (name, args) {
+        ("is", [l, r]) => arith::eval(r).and_then(|val| unify(l, &val.into_expr(r.loc()), occurs)),
+        ("=", [l, r]) => unify(l, r, occurs),
+        ("\\=", [l, r]) => unify(l, r, occurs).map(|subs| succeed_if(subs.is_empty())),
+        ("unify_with_occurs_check", [l, r]) => unify(l, r, OccursCheck::True),
+        // identical terms are equal without binding anything,
+        // so `X == Y` only holds if they are the same variable
+        ("==", [l, r]) => Ok(succeed_if(l == r)),
+        ("\\==", [l, r]) => Ok(succeed_if(l != r)),
+        ("=:=", [l, r]) => compare(l, r, |o| o.is_some_and(Ordering::is_eq)),
+        ("=\\=", [l, r]) => compare(l, r, |o| !o.is_some_and(Ordering::is_eq)),
+        ("<", [l, r]) => compare(l, r, |o| o.is_some_and(Ordering::is_lt)),
+        (">", [l, r]) => compare(l, r, |o| o.is_some_and(Ordering::is_gt)),
+        ("=<", [l, r]) => compare(l, r, |o| o.is_some_and(Ordering::is_le)),
+        (">=", [l, r]) => compare(l, r, |o| o.is_some_and(Ordering::is_ge)),
+        (
+            "var" | "nonvar" | "atom" | "number" | "integer" | "float" | "atomic" | "compound"
+            | "callable" | "is_list" | "ground",
+            [t],
+        ) => Ok(succeed_if(is_type(name, t))),
+        ("set_prolog_flag", [flag, val]) => ctx.flags.set(flag, val).map(|_| succeed_if(true)),
+        ("current_prolog_flag", [_, _]) => Ok(ctx
+            .flags
+            .get_all()
+            .into_iter()
+            .flat_map(|(flag, val)| unify_exprs(e, &goal(name, vec![flag, val], e), occurs))
+            .collect()),
+        ("op", [p, ty, names]) => ctx
+            .ops
+            .borrow_mut()
+            .op(p, ty, names)
+            .map(|_| succeed_if(true)),
+        ("current_op", [_, _, _]) => Ok(ctx
+            .ops
+            .borrow()
+            .get_all()
+            .into_iter()
+            .flat_map(|(p, ty, op)| unify_exprs(e, &goal(name, vec![p, ty, op], e), occurs))
+            .collect()),
+        _ => return None,
+    })
+}
+
+// the type tests, like `atom(foo)`.
+// they look at the term as it is now, so `var(X)` stops holding once `X` is bound.
+fn is_type(ty: &str, t: &Expr) -> bool {
+    match ty {
+        "var" => matches!(t, Expr::Var { .. }),
+        "nonvar" => !matches!(t, Expr::Var { .. }),
+        "atom" => matches!(t, Expr::Fun { args, .. } if args.is_empty()),
+        "number" => matches!(
+            t,
+            Expr::Int { .. } | Expr::BigInt { .. } | Expr::Float { .. }
+        ),
+        "integer" => matches!(t, Expr::Int { .. } | Expr::BigInt { .. }),
+        "float" => matches!(t, Expr::Float { .. }),
+        "atomic" => match t {
+            Expr::Var { .. } => false,
+            Expr::Fun { args, .. } => args.is_empty(),
+            _ => true,
+        },
+        "compound" => matches!(t, Expr::Fun { args, .. } if !args.is_empty()),
+        "callable" => matches!(t, Expr::Fun { .. }),
+        "is_list" => {
+            let mut tail = t;
+            loop {
+                match tail {
+                    Expr::Fun { name, args, .. } if name == NIL && args.is_empty() => break true,
+                    Expr::Fun { name, args, .. } if name == CONS && args.len() == 2 => {
+                        tail = &args[1]
+                    }
+                    _ => break false,
+                }
+            }
+        }
+        "ground" => crate::with_stacker(|| match t {
+            Expr::Var { .. } => false,
+            Expr::Fun { args, .. } => args.iter().all(|arg| is_type("ground", arg)),
+            _ => true,
+        }),
+        _ => unreachable!(),
+    }
+}
+
+// evaluates both sides and compares the results.
+// both sides have to be fully instantiated.
+// a NaN is not ordered with respect to anything, so `f` gets `None` for it.
+fn compare(l: &Expr, r: &Expr, f: fn(Option<Ordering>) -> bool) -> Result<Subs, ApplyError> {
+    let ord = arith::eval(l)?.compare(&arith::eval(r)?);
+    Ok(succeed_if(f(ord)))
+}
+
+// a goal that succeeds once without binding anything, or fails
+pub(crate) fn succeed_if(b: bool) -> Subs {
+    if b {
+        vec![HashMap::new()]
+    } else {
+        Vec::new()
+    }
+}
+
+// a goal with the same name and location as `e`, used to unify `e` against all the answers
+pub(crate) fn goal(name: &str, args: Vec<Expr>, e: &Expr) -> Expr {
+    Expr::Fun {
+        name: name.to_string(),
+        args,
+        loc: e.loc(),
+    }
+}
+
+pub(crate) fn unify(a: &Expr, b: &Expr, occurs: OccursCheck) -> Result<Subs, ApplyError> {
+    match unify_exprs(a, b, occurs) {
+        Ok(sub) => Ok(vec![sub]),
+        Err(ApplyError::UnifyFail) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
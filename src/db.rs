@@ -0,0 +1,374 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    builtins::{succeed_if, Subs},
+    expr::{Expr, IdAlloc, CONS, MAX_ARITY, NIL},
+    flags::OccursCheck,
+    parser::{conjuncts, Def},
+    tabling::Mode,
+    unify::{freshen, unify_exprs, ApplyError},
+};
+
+// the clauses of a predicate. a call holds on to the list it started with,
+// and changes are made to a copy of it while it does,
+// so it does not see clauses that are added or removed after it started
+// (the logical update view).
+pub(crate) type Clauses = Rc<Vec<Rc<Def>>>;
+
+#[derive(Default)]
+struct Pred {
+    clauses: Clauses,
+    // only dynamic predicates can be changed while the program runs
+    dynamic: bool,
+    // the answers of tabled predicates are kept in tables while a query runs,
+    // with how each argument is tabled
+    table: Option<Vec<Mode>>,
+}
+
+// the program. predicates are identified by their name and arity,
+// so that `foo/1` and `foo/2` are different predicates.
+pub struct Rules(RefCell<HashMap<(String, usize), Pred>>);
+
+impl Rules {
+    pub(crate) fn new() -> Self {
+        Self(RefCell::new(HashMap::new()))
+    }
+
+    pub(crate) fn get(&self, name: &str, arity: usize) -> Option<Clauses> {
+        let preds = self.0.borrow();
+        preds
+            .get(&(name.to_string(), arity))
+            .map(|pred| pred.clauses.clone())
+    }
+
+    // adds a clause of the program text.
+    // its predicate is static, unless it is declared as dynamic.
+    pub(crate) fn add(&self, def: Def) {
+        let mut preds = self.0.borrow_mut();
+        let pred = preds.entry((def.name.clone(), def.arity)).or_default();
+        Rc::make_mut(&mut pred.clauses).push(Rc::new(def));
+    }
+
+    // adds a clause while the program runs, at the start or at the end of its predicate
+    fn assert(&self, clause: &Expr, front: bool) -> Result<Subs, ApplyError> {
+        let def = to_def(clause)?;
+        let mut preds = self.0.borrow_mut();
+        let pred = self.dynamic(&mut preds, def.name.clone(), def.arity)?;
+        let clauses = Rc::make_mut(&mut pred.clauses);
+        if front {
+            clauses.insert(0, Rc::new(def));
+        } else {
+            clauses.push(Rc::new(def));
+        }
+        Ok(succeed_if(true))
+    }
+
+    // the clauses `retract(Clause)` has to look at,
+    // or `None` if there is no such predicate
+    pub(crate) fn retracting(&self, clause: &Expr) -> Result<Option<Clauses>, ApplyError> {
+        let (name, arity) = key(head(clause))?;
+        let preds = self.0.borrow();
+        match preds.get(&(name.clone(), arity)) {
+            Some(pred) if !pred.dynamic => Err(static_procedure(name, arity)),
+            Some(pred) => Ok(Some(pred.clauses.clone())),
+            None => Ok(None),
+        }
+    }
+
+    // removes the first clause starting at `from` that unifies with `clause`,
+    // giving its position and the bindings.
+    // clauses that were already removed since `clauses` was taken are skipped.
+    pub(crate) fn retract(
+        &self,
+        clause: &Expr,
+        clauses: &[Rc<Def>],
+        from: usize,
+        gen: &mut IdAlloc<u64>,
+        occurs: OccursCheck,
+    ) -> Option<(usize, HashMap<u64, Expr>)> {
+        let clause = match clause {
+            Expr::Fun { name, args, .. } if name == ":-" && args.len() == 2 => clause.clone(),
+            _ => rule(clause.clone(), Expr::atom("true", clause.loc())),
+        };
+        clauses.iter().enumerate().skip(from).find_map(|(i, def)| {
+            gen.new_clause();
+            let sub = unify_exprs(&clause, &freshen(&clause_term(def), gen), occurs).ok()?;
+            self.remove(def).then_some((i, sub))
+        })
+    }
+
+    // removes a clause, telling if it was still there
+    fn remove(&self, def: &Rc<Def>) -> bool {
+        let mut preds = self.0.borrow_mut();
+        let Some(pred) = preds.get_mut(&(def.name.clone(), def.arity)) else {
+            return false;
+        };
+        match pred.clauses.iter().position(|d| Rc::ptr_eq(d, def)) {
+            Some(i) => {
+                Rc::make_mut(&mut pred.clauses).remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // removes all the clauses whose head unifies with `head`
+    fn retract_all(
+        &self,
+        head: &Expr,
+        gen: &mut IdAlloc<u64>,
+        occurs: OccursCheck,
+    ) -> Result<Subs, ApplyError> {
+        let (name, arity) = key(head)?;
+        let mut preds = self.0.borrow_mut();
+        let pred = self.dynamic(&mut preds, name, arity)?;
+        Rc::make_mut(&mut pred.clauses).retain(|def| {
+            gen.new_clause();
+            unify_exprs(head, &freshen(&def.pat, gen), occurs).is_err()
+        });
+        Ok(succeed_if(true))
+    }
+
+    // removes a whole predicate, given as `Name/Arity`
+    fn abolish(&self, pi: &Expr) -> Result<Subs, ApplyError> {
+        let key = indicator(pi)?;
+        let mut preds = self.0.borrow_mut();
+        match preds.get(&key) {
+            Some(pred) if !pred.dynamic => Err(static_procedure(key.0, key.1)),
+            _ => {
+                preds.remove(&key);
+                Ok(succeed_if(true))
+            }
+        }
+    }
+
+    // how the arguments of `name/arity` are tabled, if it is tabled
+    pub(crate) fn table(&self, name: &str, arity: usize) -> Option<Vec<Mode>> {
+        let preds = self.0.borrow();
+        preds
+            .get(&(name.to_string(), arity))
+            .and_then(|pred| pred.table.clone())
+    }
+
+    // declares predicates as dynamic, given as `Name/Arity` or a conjunction or list of them
+    fn declare_dynamic(&self, pis: &Expr) -> Result<Subs, ApplyError> {
+        let mut preds = self.0.borrow_mut();
+        each_spec(pis, &mut |pi| {
+            preds.entry(indicator(pi)?).or_default().dynamic = true;
+            Ok(())
+        })?;
+        Ok(succeed_if(true))
+    }
+
+    // declares predicates as tabled, given as `Name/Arity` or with a mode for every argument,
+    // like `path(_, _, min)`
+    fn declare_table(&self, specs: &Expr) -> Result<Subs, ApplyError> {
+        let mut preds = self.0.borrow_mut();
+        each_spec(specs, &mut |spec| {
+            let (key, modes) = table_spec(spec)?;
+            preds.entry(key).or_default().table = Some(modes);
+            Ok(())
+        })?;
+        Ok(succeed_if(true))
+    }
+
+    // the predicate `name/arity` if it can be changed, making it if it does not exist yet
+    fn dynamic<'a>(
+        &self,
+        preds: &'a mut HashMap<(String, usize), Pred>,
+        name: String,
+        arity: usize,
+    ) -> Result<&'a mut Pred, ApplyError> {
+        let pred = preds.entry((name.clone(), arity)).or_insert_with(|| Pred {
+            dynamic: true,
+            ..Pred::default()
+        });
+        if pred.dynamic {
+            Ok(pred)
+        } else {
+            Err(static_procedure(name, arity))
+        }
+    }
+}
+
+// runs the predicates that change the program, except for `retract/1`,
+// which is run by the solver since it can be retried.
+// returns `None` for any other goal.
+pub(crate) fn call(
+    defs: &Rules,
+    e: &Expr,
+    gen: &mut IdAlloc<u64>,
+    occurs: OccursCheck,
+) -> Option<Result<Subs, ApplyError>> {
+    let (name, args) = match e {
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        _ => return None,
+    };
+    Some(match (name, args) {
+        ("assert" | "assertz", [c]) => defs.assert(c, false),
+        ("asserta", [c]) => defs.assert(c, true),
+        ("retractall", [h]) => defs.retract_all(h, gen, occurs),
+        ("abolish", [pi]) => defs.abolish(pi),
+        ("dynamic", [pis]) => defs.declare_dynamic(pis),
+        ("table", [specs]) => defs.declare_table(specs),
+        _ => return None,
+    })
+}
+
+// turns a term like `H :- B` or `H` into a clause
+fn to_def(clause: &Expr) -> Result<Def, ApplyError> {
+    let pat = head(clause);
+    let (name, arity) = key(pat)?;
+    let mut rep = Vec::new();
+    if let Expr::Fun { name, args, .. } = clause {
+        if name == ":-" && args.len() == 2 {
+            conjuncts(args[1].clone(), &mut rep);
+        }
+    }
+    // a variable goal `G` is run as `call(G)`, as in a clause of the program text,
+    // so only the goals that are numbers or strings are not callable
+    if let Some(goal) = rep.iter().find(|e| !matches!(e, Expr::Fun { .. })) {
+        return Err(ApplyError::Type("callable", goal.clone()));
+    }
+    rep.reverse();
+    Ok(Def {
+        name,
+        arity,
+        loc: pat.loc(),
+        pat: pat.clone(),
+        rep,
+    })
+}
+
+// the clause as the term `H :- B`, where `B` is `true` for a fact
+fn clause_term(def: &Def) -> Expr {
+    let loc = def.loc;
+    let body = def
+        .rep
+        .iter()
+        .cloned()
+        .reduce(|rest, e| Expr::Fun {
+            name: ",".to_string(),
+            args: vec![e, rest],
+            loc,
+        })
+        .unwrap_or_else(|| Expr::atom("true", loc));
+    rule(def.pat.clone(), body)
+}
+
+fn rule(head: Expr, body: Expr) -> Expr {
+    let loc = head.loc();
+    Expr::Fun {
+        name: ":-".to_string(),
+        args: vec![head, body],
+        loc,
+    }
+}
+
+fn head(clause: &Expr) -> &Expr {
+    match clause {
+        Expr::Fun { name, args, .. } if name == ":-" && args.len() == 2 => &args[0],
+        _ => clause,
+    }
+}
+
+// the name and arity of the predicate a head belongs to
+fn key(head: &Expr) -> Result<(String, usize), ApplyError> {
+    match head {
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Fun { name, args, .. } => Ok((name.clone(), args.len())),
+        _ => Err(ApplyError::Type("callable", head.clone())),
+    }
+}
+
+// runs `f` on every item of a conjunction or list of declarations
+fn each_spec(
+    specs: &Expr,
+    f: &mut impl FnMut(&Expr) -> Result<(), ApplyError>,
+) -> Result<(), ApplyError> {
+    match specs {
+        Expr::Fun { name, args, .. } if (name == "," || name == CONS) && args.len() == 2 => {
+            each_spec(&args[0], f)?;
+            each_spec(&args[1], f)
+        }
+        Expr::Fun { name, args, .. } if name == NIL && args.is_empty() => Ok(()),
+        _ => f(specs),
+    }
+}
+
+// reads what `:- table` is given for a predicate: `Name/Arity`, where every argument
+// is indexed, or a term like `path(_, _, min)` with the mode of every argument
+fn table_spec(spec: &Expr) -> Result<((String, usize), Vec<Mode>), ApplyError> {
+    let (name, args) = match spec {
+        Expr::Fun { name, args, .. } if !(name == "/" && args.len() == 2) => (name, args),
+        _ => {
+            let key = indicator(spec)?;
+            if key.1 > MAX_ARITY {
+                return Err(ApplyError::Representation("max_arity"));
+            }
+            let modes = vec![Mode::Index; key.1];
+            return Ok((key, modes));
+        }
+    };
+    let modes = args
+        .iter()
+        .map(|arg| match arg {
+            Expr::Var { .. } => Ok(Mode::Index),
+            Expr::Fun { name, args, .. } => match (name.as_str(), args.as_slice()) {
+                ("index", []) => Ok(Mode::Index),
+                ("min", []) => Ok(Mode::Min),
+                ("max", []) => Ok(Mode::Max),
+                // the join is `Name/3`, which can be given as just `Name`
+                ("lattice", [pi]) => match pi {
+                    Expr::Fun { name, args, .. } if args.is_empty() => {
+                        Ok(Mode::Lattice(name.clone()))
+                    }
+                    _ => match indicator(pi)? {
+                        (name, 3) => Ok(Mode::Lattice(name)),
+                        _ => Err(ApplyError::Domain("lattice", pi.clone())),
+                    },
+                },
+                _ => Err(ApplyError::Domain("table_mode", arg.clone())),
+            },
+            _ => Err(ApplyError::Domain("table_mode", arg.clone())),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(((name.clone(), args.len()), modes))
+}
+
+// reads a predicate indicator `Name/Arity`
+fn indicator(pi: &Expr) -> Result<(String, usize), ApplyError> {
+    match pi {
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Fun { name, args, .. } if name == "/" && args.len() == 2 => {
+            match (&args[0], &args[1]) {
+                (Expr::Var { .. }, _) | (_, Expr::Var { .. }) => Err(ApplyError::Instantiation),
+                (Expr::Fun { name, args, .. }, arity) if args.is_empty() => match arity {
+                    Expr::Int { val, .. } if *val >= 0 => Ok((name.clone(), *val as usize)),
+                    Expr::Int { .. } => {
+                        Err(ApplyError::Domain("not_less_than_zero", arity.clone()))
+                    }
+                    _ => Err(ApplyError::Type("integer", arity.clone())),
+                },
+                (name, _) => Err(ApplyError::Type("atom", name.clone())),
+            }
+        }
+        _ => Err(ApplyError::Type("predicate_indicator", pi.clone())),
+    }
+}
+
+fn static_procedure(name: String, arity: usize) -> ApplyError {
+    let loc = Default::default();
+    let pi = Expr::Fun {
+        name: "/".to_string(),
+        args: vec![
+            Expr::atom(&name, loc),
+            Expr::Int {
+                val: arity as i64,
+                loc,
+            },
+        ],
+        loc,
+    };
+    ApplyError::Permission("modify", "static_procedure", pi)
+}
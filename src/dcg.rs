@@ -0,0 +1,132 @@
+use std::hash::Hash;
+
+use crate::{
+    error::Loc,
+    expr::{Expr, IdAlloc, CONS, NIL},
+    terms::list,
+    unify::ApplyError,
+};
+
+// turns a grammar rule `Head --> Body` into a clause `Head(S0, S) :- Body(S0, S)`,
+// where the text `Body` parses is the difference between the lists `S0` and `S`.
+// a head like `Head, Pushback --> Body` puts `Pushback` back in front of what is left.
+pub(crate) fn translate<T: Eq + Hash>(
+    head: &Expr,
+    body: &Expr,
+    id: &mut IdAlloc<T>,
+) -> Result<Expr, ApplyError> {
+    let loc = head.loc();
+    let (s0, s) = (var(id, loc), var(id, loc));
+    let (head, body) = match head {
+        Expr::Fun { name, args, .. } if name == "," && args.len() == 2 => {
+            let mid = var(id, loc);
+            let pushback = terminals(&args[1], s.clone(), mid.clone())?;
+            let body = body_goal(body, s0.clone(), mid, id)?;
+            (&args[0], and(body, pushback))
+        }
+        _ => (head, body_goal(body, s0.clone(), s.clone(), id)?),
+    };
+    let head = match head {
+        Expr::Var { .. } => return Err(ApplyError::Instantiation),
+        Expr::Fun { .. } => extend(head, s0, s),
+        _ => return Err(ApplyError::Type("callable", head.clone())),
+    };
+    Ok(fun(":-", vec![head, body], loc))
+}
+
+// the goal that parses the grammar body `b` from `s0`, leaving `s`
+pub(crate) fn body_goal<T: Eq + Hash>(
+    b: &Expr,
+    s0: Expr,
+    s: Expr,
+    id: &mut IdAlloc<T>,
+) -> Result<Expr, ApplyError> {
+    let loc = b.loc();
+    let (name, args) = match b {
+        // a variable is a body that is only known once the rule runs
+        Expr::Var { .. } => return Ok(fun("phrase", vec![b.clone(), s0, s], loc)),
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        Expr::Str { .. } => return terminals(b, s0, s),
+        _ => return Err(ApplyError::Type("callable", b.clone())),
+    };
+    Ok(match (name, args) {
+        (",", [l, r]) => {
+            let mid = var(id, loc);
+            let l = body_goal(l, s0, mid.clone(), id)?;
+            and(l, body_goal(r, mid, s, id)?)
+        }
+        (";" | "|", [l, r]) => {
+            let l = body_goal(l, s0.clone(), s.clone(), id)?;
+            fun(";", vec![l, body_goal(r, s0, s, id)?], loc)
+        }
+        ("->", [c, then]) => {
+            let mid = var(id, loc);
+            let c = body_goal(c, s0, mid.clone(), id)?;
+            fun("->", vec![c, body_goal(then, mid, s, id)?], loc)
+        }
+        // nothing is parsed by a goal that does not hold
+        ("\\+", [g]) => {
+            let g = body_goal(g, s0.clone(), var(id, loc), id)?;
+            and(fun("\\+", vec![g], loc), unify(s0, s))
+        }
+        // goals in braces are called as they are, and parse nothing
+        ("{}", [g]) => and(g.clone(), unify(s0, s)),
+        ("{}", []) => unify(s0, s),
+        ("!", []) => and(b.clone(), unify(s0, s)),
+        (NIL, []) | (CONS, [_, _]) => terminals(b, s0, s)?,
+        _ => extend(b, s0, s),
+    })
+}
+
+// `s0` starts with the terminals in the list `t`, followed by `s`
+fn terminals(t: &Expr, s0: Expr, s: Expr) -> Result<Expr, ApplyError> {
+    let loc = t.loc();
+    let items = match t {
+        // a string as it is when the `double_quotes` flag is `string`
+        Expr::Str { val, .. } => val
+            .chars()
+            .map(|c| Expr::Int { val: c as i64, loc })
+            .collect(),
+        _ => list(t)?.into_iter().cloned().collect(),
+    };
+    Ok(unify(s0, Expr::list(items, s)))
+}
+
+// a non-terminal with the 2 extra arguments for the lists
+fn extend(e: &Expr, s0: Expr, s: Expr) -> Expr {
+    match e {
+        Expr::Fun { name, args, loc } => {
+            let args = args.iter().cloned().chain([s0, s]).collect();
+            fun(name, args, *loc)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn and(a: Expr, b: Expr) -> Expr {
+    let loc = a.loc();
+    fun(",", vec![a, b], loc)
+}
+
+fn unify(a: Expr, b: Expr) -> Expr {
+    let loc = a.loc();
+    fun("=", vec![a, b], loc)
+}
+
+fn fun(name: &str, args: Vec<Expr>, loc: Loc) -> Expr {
+    Expr::Fun {
+        name: name.to_string(),
+        args,
+        loc,
+    }
+}
+
+// a new variable for one of the lists
+fn var<T: Eq + Hash>(id: &mut IdAlloc<T>, loc: Loc) -> Expr {
+    let id = id.fresh();
+    Expr::Var {
+        name: format!("_S{}", id),
+        id,
+        loc,
+    }
+}
@@ -48,6 +48,9 @@ impl Default for Loc {
 pub enum ErrorTy {
     SyntaxError,
     CExprError,
+    Warning,
+    // a ball thrown by a goal that nothing caught
+    Uncaught,
 }
 
 impl Display for ErrorTy {
@@ -56,6 +59,8 @@ impl Display for ErrorTy {
         match self {
             SyntaxError => write!(fmt, "syntax error"),
             CExprError => write!(fmt, "closedness check error"),
+            Warning => write!(fmt, "warning"),
+            Uncaught => write!(fmt, "uncaught exception"),
         }
     }
 }
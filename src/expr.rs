@@ -1,5 +1,14 @@
-use crate::error::Loc;
-use std::{collections::HashMap, fmt::Display, hash::Hash};
+use crate::{arith, error::Loc, ops::Ops, token::is_symbol};
+use num_bigint::BigInt;
+use std::{cmp::Ordering, collections::HashMap, fmt::Display, hash::Hash};
+
+// the functor of list cells, `[a, b]` is `'.'(a, '.'(b, []))`
+pub(crate) const CONS: &str = ".";
+// the empty list
+pub(crate) const NIL: &str = "[]";
+// the most arguments a term made from an arity that is given can have,
+// so that a huge one is an error instead of running out of memory
+pub(crate) const MAX_ARITY: usize = 1 << 20;
 
 // used to allocate id for variables to differentiate
 // between variables from different clauses
@@ -15,6 +24,11 @@ impl<T: Eq + Hash> IdAlloc<T> {
             self.1
         })
     }
+    // an id that is not given to anything else
+    pub(crate) fn fresh(&mut self) -> u64 {
+        self.1 += 1;
+        self.1
+    }
     pub(crate) fn new_clause(&mut self) {
         self.0.clear();
     }
@@ -23,7 +37,7 @@ impl<T: Eq + Hash> IdAlloc<T> {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum Expr {
     Fun {
         name: String,
@@ -35,6 +49,148 @@ pub enum Expr {
         id: u64,
         loc: Loc,
     },
+    Int {
+        val: i64,
+        loc: Loc,
+    },
+    // integers that are too big for an `i64`.
+    // smaller ones are always represented by `Expr::Int`.
+    BigInt {
+        val: BigInt,
+        loc: Loc,
+    },
+    Float {
+        val: f64,
+        loc: Loc,
+    },
+    // a double-quoted text, when the `double_quotes` flag is `string`
+    Str {
+        val: String,
+        loc: Loc,
+    },
+}
+
+impl Expr {
+    pub(crate) fn atom(name: &str, loc: Loc) -> Expr {
+        Expr::Fun {
+            name: name.to_string(),
+            args: Vec::new(),
+            loc,
+        }
+    }
+
+    // builds the list `[items | tail]` out of cons cells
+    pub(crate) fn list(items: Vec<Expr>, tail: Expr) -> Expr {
+        items.into_iter().rev().fold(tail, |tail, item| Expr::Fun {
+            name: CONS.to_string(),
+            loc: item.loc(),
+            args: vec![item, tail],
+        })
+    }
+
+    // compares two terms in the standard order:
+    // variables < numbers < atoms < strings < compound terms.
+    // numbers are compared by value, with a float coming before an equal integer.
+    // compound terms are compared by arity, then name, then their arguments from left to right.
+    pub(crate) fn compare(&self, other: &Expr) -> Ordering {
+        let rank = |e: &Expr| match e {
+            Expr::Var { .. } => 0,
+            Expr::Int { .. } | Expr::BigInt { .. } | Expr::Float { .. } => 1,
+            Expr::Fun { args, .. } if args.is_empty() => 2,
+            Expr::Str { .. } => 3,
+            Expr::Fun { .. } => 4,
+        };
+        crate::with_stacker(|| match (self, other) {
+            (Expr::Var { id, .. }, Expr::Var { id: id2, .. }) => id.cmp(id2),
+            (
+                Expr::Fun { name, args, .. },
+                Expr::Fun {
+                    name: name2,
+                    args: args2,
+                    ..
+                },
+            ) => args
+                .len()
+                .cmp(&args2.len())
+                .then_with(|| name.cmp(name2))
+                .then_with(|| {
+                    let mut pairs = args.iter().zip(args2);
+                    pairs
+                        .find_map(|(a, b)| Some(a.compare(b)).filter(|o| o.is_ne()))
+                        .unwrap_or(Ordering::Equal)
+                }),
+            (Expr::Str { val, .. }, Expr::Str { val: val2, .. }) => val.cmp(val2),
+            (a, b) if rank(a) == 1 && rank(b) == 1 => {
+                let is_float = |e: &Expr| matches!(e, Expr::Float { .. });
+                let (x, y) = (arith::eval(a).unwrap(), arith::eval(b).unwrap());
+                x.compare(&y)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| is_float(b).cmp(&is_float(a)))
+            }
+            (a, b) => rank(a).cmp(&rank(b)),
+        })
+    }
+
+    pub(crate) fn loc(&self) -> Loc {
+        match self {
+            Expr::Fun { loc, .. }
+            | Expr::Var { loc, .. }
+            | Expr::Int { loc, .. }
+            | Expr::BigInt { loc, .. }
+            | Expr::Float { loc, .. }
+            | Expr::Str { loc, .. } => *loc,
+        }
+    }
+}
+
+// terms are cloned with `with_stacker`, since long lists like the codes of a big atom
+// are nested as deep as they are long
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        crate::with_stacker(|| match self {
+            Expr::Fun { name, args, loc } => Expr::Fun {
+                name: name.clone(),
+                args: args.clone(),
+                loc: *loc,
+            },
+            Expr::Var { name, id, loc } => Expr::Var {
+                name: name.clone(),
+                id: *id,
+                loc: *loc,
+            },
+            Expr::Int { val, loc } => Expr::Int {
+                val: *val,
+                loc: *loc,
+            },
+            Expr::BigInt { val, loc } => Expr::BigInt {
+                val: val.clone(),
+                loc: *loc,
+            },
+            Expr::Float { val, loc } => Expr::Float {
+                val: *val,
+                loc: *loc,
+            },
+            Expr::Str { val, loc } => Expr::Str {
+                val: val.clone(),
+                loc: *loc,
+            },
+        })
+    }
+}
+
+// long lists are dropped one cell at a time, for the same reason
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let Expr::Fun { args, .. } = self else {
+            return;
+        };
+        let mut rest = std::mem::take(args);
+        while let Some(mut e) = rest.pop() {
+            if let Expr::Fun { args, .. } = &mut e {
+                rest.append(args);
+            }
+        }
+    }
 }
 
 impl Default for Expr {
@@ -49,7 +205,7 @@ impl Default for Expr {
 
 impl PartialEq for Expr {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
+        crate::with_stacker(|| match (self, other) {
             (Expr::Var { id, .. }, Expr::Var { id: id2, .. }) => id == id2,
             (
                 Expr::Fun { name, args, .. },
@@ -59,28 +215,182 @@ impl PartialEq for Expr {
                     ..
                 },
             ) => name == name2 && args == args2,
+            (Expr::Int { val, .. }, Expr::Int { val: val2, .. }) => val == val2,
+            (Expr::BigInt { val, .. }, Expr::BigInt { val: val2, .. }) => val == val2,
+            (Expr::Float { val, .. }, Expr::Float { val: val2, .. }) => val == val2,
+            (Expr::Str { val, .. }, Expr::Str { val: val2, .. }) => val == val2,
             _ => false,
-        }
+        })
     }
 }
 
 impl Display for Expr {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        crate::with_stacker(|| match self {
-            Expr::Fun { name, args, .. } if args.is_empty() => write!(fmt, "{}", name),
+        write!(fmt, "{}", Pretty(self, Ops::standard(), 1200))
+    }
+}
+
+// an expression written with the operators of `ops`, so that it can be read back
+// as an argument of priority at most the last field.
+// operators that bind looser than that get parentheses around them.
+pub(crate) struct Pretty<'a>(pub(crate) &'a Expr, pub(crate) &'a Ops, pub(crate) u32);
+
+impl Display for Pretty<'_> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Pretty(e, ops, max) = *self;
+        let arg = |e| Pretty(e, ops, 999);
+        crate::with_stacker(|| match e {
+            Expr::Fun { name, args, .. } if args.is_empty() => {
+                // a lone operator as the argument of another one, like in `X = (:-)`.
+                // arguments of compound terms and lists don't need the parentheses.
+                if max < 999 && ops.priority(name).is_some_and(|p| p > max) {
+                    write!(fmt, "({})", Atom(name))
+                } else {
+                    write!(fmt, "{}", Atom(name))
+                }
+            }
+            // lists are printed back in list notation, e.g. `[a, b|T]`
+            Expr::Fun { name, args, .. } if name == CONS && args.len() == 2 => {
+                write!(fmt, "[{}", arg(&args[0]))?;
+                let mut tail = &args[1];
+                loop {
+                    match tail {
+                        Expr::Fun { name, args, .. } if name == CONS && args.len() == 2 => {
+                            write!(fmt, ", {}", arg(&args[0]))?;
+                            tail = &args[1];
+                        }
+                        Expr::Fun { name, args, .. } if name == NIL && args.is_empty() => break,
+                        _ => {
+                            write!(fmt, "|{}", arg(tail))?;
+                            break;
+                        }
+                    }
+                }
+                write!(fmt, "]")
+            }
+            Expr::Fun { name, args, .. } if name == "{}" && args.len() == 1 => {
+                write!(fmt, "{{{}}}", Pretty(&args[0], ops, 1200))
+            }
             Expr::Fun { name, args, .. } => {
-                write!(fmt, "{}(", name)?;
-                let mut comma = false;
-                for arg in args {
-                    if comma {
-                        write!(fmt, ", ")?;
+                let op = match args.as_slice() {
+                    [l, r] => ops.infix(name).map(|op| (op, Some(l), Some(r))),
+                    [x] => ops
+                        .prefix(name)
+                        .map(|op| (op, None, Some(x)))
+                        .or_else(|| ops.postfix(name).map(|op| (op, Some(x), None))),
+                    _ => None,
+                };
+                let Some(((p, ty), l, r)) = op else {
+                    write!(fmt, "{}(", Atom(name))?;
+                    let mut comma = false;
+                    for a in args {
+                        if comma {
+                            write!(fmt, ", ")?;
+                        }
+                        write!(fmt, "{}", arg(a))?;
+                        comma = true;
                     }
-                    write!(fmt, "{}", arg)?;
-                    comma = true;
+                    return write!(fmt, ")");
+                };
+
+                let (lmax, rmax) = ty.arg_priorities(p);
+                if p > max {
+                    write!(fmt, "(")?;
+                }
+                match (l, r) {
+                    (Some(l), Some(r)) if name == "," => {
+                        write!(fmt, "{}, {}", Pretty(l, ops, lmax), Pretty(r, ops, rmax))?
+                    }
+                    (Some(l), Some(r)) => write!(
+                        fmt,
+                        "{} {} {}",
+                        Pretty(l, ops, lmax),
+                        Atom(name),
+                        Pretty(r, ops, rmax)
+                    )?,
+                    (None, Some(r)) => {
+                        let r = Pretty(r, ops, rmax).to_string();
+                        // keep the operator apart from what could be read together with it:
+                        // `- 1` is not the number `-1`, `- -a` is not `--(a)`,
+                        // and `- (a, b)` is not `-(a, b)`
+                        let apart = name.starts_with(char::is_alphanumeric)
+                            || r.starts_with(|c: char| {
+                                is_symbol(c) || c.is_ascii_digit() || c == '('
+                            });
+                        write!(fmt, "{}{}{}", Atom(name), if apart { " " } else { "" }, r)?
+                    }
+                    (Some(l), None) => write!(fmt, "{} {}", Pretty(l, ops, lmax), Atom(name))?,
+                    (None, None) => unreachable!(),
+                }
+                if p > max {
+                    write!(fmt, ")")?;
                 }
-                write!(fmt, ")")
+                Ok(())
             }
             Expr::Var { name, .. } => write!(fmt, "{}", name),
+            Expr::Int { val, .. } => write!(fmt, "{}", val),
+            Expr::BigInt { val, .. } => write!(fmt, "{}", val),
+            Expr::Float { val, .. } => write!(fmt, "{}", fmt_float(*val)),
+            Expr::Str { val, .. } => write_quoted(fmt, val, '"'),
         })
     }
 }
+
+// an atom, written so that it can be read back in:
+// plain names like `foo` or `=<` as they are, anything else quoted.
+pub(crate) struct Atom<'a>(pub(crate) &'a str);
+
+impl Display for Atom<'_> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !needs_quotes(self.0) {
+            return write!(fmt, "{}", self.0);
+        }
+        write_quoted(fmt, self.0, '\'')
+    }
+}
+
+// writes the text between `q` quotes, escaping what is needed to read it back in
+fn write_quoted(fmt: &mut std::fmt::Formatter<'_>, s: &str, q: char) -> std::fmt::Result {
+    write!(fmt, "{}", q)?;
+    for c in s.chars() {
+        match c {
+            '\\' => write!(fmt, "\\\\")?,
+            '\n' => write!(fmt, "\\n")?,
+            '\t' => write!(fmt, "\\t")?,
+            c if c == q => write!(fmt, "\\{}", q)?,
+            c if c.is_control() => write!(fmt, "\\x{:x}\\", c as u32)?,
+            c => write!(fmt, "{}", c)?,
+        }
+    }
+    write!(fmt, "{}", q)
+}
+
+fn needs_quotes(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        None => true,
+        Some(c) if c.is_alphabetic() && !c.is_uppercase() => {
+            !chars.all(|c| c.is_alphanumeric() || c == '_')
+        }
+        // solo atoms
+        _ if [NIL, "{}", "!", ";"].contains(&s) => false,
+        // a lone `.` would end the clause
+        _ => s == "." || !s.chars().all(is_symbol),
+    }
+}
+
+// formats a float so that it is read back as the same float,
+// so it always has a fractional part: `1.0`, `1.0e20`, `1.0Inf`, `1.5NaN`.
+fn fmt_float(val: f64) -> String {
+    if val.is_nan() {
+        return "1.5NaN".to_string();
+    }
+    if val.is_infinite() {
+        return if val > 0.0 { "1.0Inf" } else { "-1.0Inf" }.to_string();
+    }
+    let s = format!("{:?}", val);
+    match s.find('e') {
+        Some(i) if !s[..i].contains('.') => format!("{}.0{}", &s[..i], &s[i..]),
+        _ => s,
+    }
+}
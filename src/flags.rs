@@ -0,0 +1,109 @@
+use std::cell::Cell;
+
+use crate::{error::Loc, expr::Expr, unify::ApplyError};
+
+// what a double-quoted text like `"abc"` is read as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DoubleQuotes {
+    // a list of character codes, `[97, 98, 99]`
+    Codes,
+    // a list of one-character atoms, `[a, b, c]`
+    Chars,
+    // an atom, `abc`
+    Atom,
+    // a dedicated string object
+    String,
+}
+
+const DOUBLE_QUOTES: [(&str, DoubleQuotes); 4] = [
+    ("codes", DoubleQuotes::Codes),
+    ("chars", DoubleQuotes::Chars),
+    ("atom", DoubleQuotes::Atom),
+    ("string", DoubleQuotes::String),
+];
+
+// what happens when a variable would be bound to a term that contains it,
+// like `X` in `X = f(X)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OccursCheck {
+    // it is bound anyway, which makes an infinite term
+    False,
+    // the unification fails
+    True,
+    // an `occurs_check` error is raised
+    Error,
+}
+
+const OCCURS_CHECK: [(&str, OccursCheck); 3] = [
+    ("false", OccursCheck::False),
+    ("true", OccursCheck::True),
+    ("error", OccursCheck::Error),
+];
+
+// the prolog flags, which can be changed while the program is running
+// with `set_prolog_flag/2`.
+pub(crate) struct Flags {
+    pub(crate) double_quotes: Cell<DoubleQuotes>,
+    pub(crate) occurs_check: Cell<OccursCheck>,
+}
+
+impl Flags {
+    pub(crate) fn new() -> Self {
+        Self {
+            double_quotes: Cell::new(DoubleQuotes::Codes),
+            occurs_check: Cell::new(OccursCheck::False),
+        }
+    }
+
+    // sets a flag, as in `set_prolog_flag(double_quotes, atom)`
+    pub(crate) fn set(&self, flag: &Expr, val: &Expr) -> Result<(), ApplyError> {
+        let (flag, val) = match (flag, val) {
+            (Expr::Var { .. }, _) | (_, Expr::Var { .. }) => return Err(ApplyError::Instantiation),
+            (Expr::Fun { name, args, .. }, Expr::Fun { name: val, .. }) if args.is_empty() => {
+                (name.as_str(), val.as_str())
+            }
+            (Expr::Fun { args, .. }, _) if args.is_empty() => {
+                return Err(ApplyError::Domain("flag_value", val.clone()))
+            }
+            _ => return Err(ApplyError::Type("atom", flag.clone())),
+        };
+        match flag {
+            "double_quotes" => {
+                let (_, dq) = DOUBLE_QUOTES
+                    .iter()
+                    .find(|(name, _)| *name == val)
+                    .ok_or_else(|| ApplyError::Domain("flag_value", atom(val)))?;
+                self.double_quotes.set(*dq);
+            }
+            "occurs_check" => {
+                let (_, oc) = OCCURS_CHECK
+                    .iter()
+                    .find(|(name, _)| *name == val)
+                    .ok_or_else(|| ApplyError::Domain("flag_value", atom(val)))?;
+                self.occurs_check.set(*oc);
+            }
+            _ => return Err(ApplyError::Domain("prolog_flag", atom(flag))),
+        }
+        Ok(())
+    }
+
+    // all the flags with their current values, as enumerated by `current_prolog_flag/2`
+    pub(crate) fn get_all(&self) -> Vec<(Expr, Expr)> {
+        let (dq, _) = DOUBLE_QUOTES
+            .iter()
+            .find(|(_, dq)| *dq == self.double_quotes.get())
+            .unwrap();
+        let (oc, _) = OCCURS_CHECK
+            .iter()
+            .find(|(_, oc)| *oc == self.occurs_check.get())
+            .unwrap();
+        vec![
+            (atom("double_quotes"), atom(dq)),
+            (atom("occurs_check"), atom(oc)),
+        ]
+    }
+}
+
+fn atom(name: &str) -> Expr {
+    Expr::atom(name, Loc::default())
+}
@@ -1,41 +1,88 @@
-use error::Error;
-use expr::{Expr, IdAlloc};
-use parser::Def;
-use std::collections::HashMap;
+use error::{Error, ErrorTy};
+use expr::{Expr, IdAlloc, Pretty};
+use flags::Flags;
+use ops::Ops;
+use parser::{Def, Item};
+use solve::Solver;
+use std::{cell::RefCell, collections::HashMap};
 use token::TokenTy;
-use unify::{substitute_and_freshen, ApplyError};
 
+mod arith;
+mod atoms;
+mod builtins;
+mod db;
+mod dcg;
 mod error;
 mod expr;
+mod flags;
+mod ops;
 mod parser;
+mod solutions;
+mod solve;
+mod tabling;
+mod terms;
 mod token;
 mod unify;
 
-pub type Rules = HashMap<String, Vec<Def>>;
-type Sols<'a> = Box<dyn Iterator<Item = HashMap<String, Expr>> + 'a>;
+pub use db::Rules;
+// the answers to a query, until a ball is thrown that is not caught
+type Sols<'a> = Box<dyn Iterator<Item = Result<HashMap<String, Expr>, Expr>> + 'a>;
 
 pub struct Context {
     id: IdAlloc<String>,
+    flags: Flags,
+    // the operators terms are read and written with, which `op/3` can change
+    ops: RefCell<Ops>,
 }
 
 impl Context {
     pub fn new() -> Self {
         Self {
             id: IdAlloc::new(0),
+            flags: Flags::new(),
+            ops: RefCell::new(Ops::new()),
         }
     }
 
     // parse the "program" from the file
     pub fn parse(&mut self, src: String) -> Result<Rules, Error> {
         let scanner = token::Scanner::new(&src);
-        let mut parser = parser::Parser::new(scanner);
-        let mut defs: HashMap<_, Vec<Def>> = HashMap::new();
+        let mut parser = parser::Parser::new(scanner, &self.flags, &self.ops);
+        let defs = Rules::new();
 
         loop {
             self.id.new_clause();
-            if let Some(mut def) = parser.parse_def(&mut self.id)? {
+            let item = parser.parse_item(&mut self.id)?;
+            if let Some(Item::Directive(goal)) = item {
+                // directives are run right away, and only see the clauses above them
+                let res = Solver::new(&defs, self, vec![goal.clone()], HashMap::new()).next();
+                match res {
+                    Some(Ok(_)) => {}
+                    Some(Err(ball)) => Error {
+                        loc: goal.loc(),
+                        ty: ErrorTy::Uncaught,
+                        desc: format!("{} in directive {}", ball, goal),
+                    }
+                    .report(),
+                    None => Error {
+                        loc: goal.loc(),
+                        ty: ErrorTy::Warning,
+                        desc: format!("directive {} failed", goal),
+                    }
+                    .report(),
+                }
+            } else if let Some(Item::Def(mut def)) = item {
+                let singles = singletons(&def);
+                if !singles.is_empty() {
+                    Error {
+                        loc: def.loc,
+                        ty: ErrorTy::Warning,
+                        desc: format!("singleton variables: {}", singles.join(", ")),
+                    }
+                    .report();
+                }
                 def.rep.reverse();
-                defs.entry(def.name.clone()).or_default().push(def);
+                defs.add(def);
             } else {
                 break;
             }
@@ -47,9 +94,8 @@ impl Context {
     pub fn parse_clause(&mut self, src: String) -> Result<Vec<Expr>, Error> {
         self.id.new_clause();
         let scanner = token::Scanner::new(&src);
-        let mut parser = parser::Parser::new(scanner);
-        let mut e = parser.parse_clause(&mut self.id)?;
-        e.reverse();
+        let mut parser = parser::Parser::new(scanner, &self.flags, &self.ops);
+        let e = parser.parse_clause(&mut self.id)?;
         parser.sc.expect_token(TokenTy::Period)?;
         parser.sc.expect_token(TokenTy::Eof)?;
 
@@ -57,29 +103,35 @@ impl Context {
     }
 
     // run the program on the input
-    pub fn apply<'a>(&mut self, defs: &'a Rules, e: &'a [Expr]) -> SolsPrinter<'a> {
+    pub fn apply<'a>(&'a self, defs: &'a Rules, e: &'a [Expr]) -> SolsPrinter<'a> {
         let mut qvars = HashMap::new();
         let mut order = Vec::new();
-        vars(&mut qvars, &mut order, &e);
-        match apply_internal(self.id.get_next(), defs, e.to_vec(), qvars) {
-            Ok(sols) => SolsPrinter::new(sols, order),
-            _ => SolsPrinter::new(Box::new(std::iter::empty()), order),
-        }
+        vars(&mut qvars, &mut order, e);
+        let sols = Solver::new(defs, self, e.to_vec(), qvars);
+        SolsPrinter::new(Box::new(sols), order, self)
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 pub struct SolsPrinter<'a> {
-    curr: Option<HashMap<String, Expr>>,
+    curr: Option<Result<HashMap<String, Expr>, Expr>>,
     sols: Sols<'a>,
     order: Vec<&'a str>,
+    ctx: &'a Context,
 }
 
 impl<'a> SolsPrinter<'a> {
-    pub fn new(mut sols: Sols<'a>, order: Vec<&'a str>) -> Self {
+    pub fn new(mut sols: Sols<'a>, order: Vec<&'a str>, ctx: &'a Context) -> Self {
         Self {
             curr: sols.next(),
             sols,
             order,
+            ctx,
         }
     }
     // print the solution if any
@@ -90,7 +142,16 @@ impl<'a> SolsPrinter<'a> {
     //  would be (roughly) represented as
     //    [{X: state, Y: run}, {X: state, Y: walk}]
     pub fn print_next_sol(&mut self) -> bool {
-        if let Some(mut sol) = self.curr.take() {
+        if let Some(Err(ball)) = &self.curr {
+            Error {
+                loc: ball.loc(),
+                ty: ErrorTy::Uncaught,
+                desc: ball.to_string(),
+            }
+            .report();
+            return false;
+        }
+        if let Some(Ok(mut sol)) = self.curr.take() {
             let mut comma = false;
             for v in &self.order {
                 let e = &sol[*v];
@@ -105,7 +166,8 @@ impl<'a> SolsPrinter<'a> {
                 if comma {
                     print!(", ");
                 }
-                print!("{} = {}", v, e);
+                // `X = (a :- b)`, the value is an argument of `=`
+                print!("{} = {}", v, Pretty(e, &self.ctx.ops.borrow(), 699));
                 comma = true;
             }
             // when the query has no variables, the binding set would be empty.
@@ -129,51 +191,6 @@ impl<'a> SolsPrinter<'a> {
     }
 }
 
-// recursive implementation of the selection + SLD algorithm + backtracing
-fn apply_internal<'a>(
-    gen: u64,
-    defs: &'a Rules,
-    mut e: Vec<Expr>,
-    qvars: HashMap<String, Expr>,
-) -> Result<Sols<'a>, ApplyError> {
-    with_stacker(move || {
-        let curr_e = match e.pop() {
-            Some(e) => e,
-            _ => return Ok(Box::new(std::iter::once(qvars)) as _),
-        };
-        let f_defs = match &curr_e {
-            Expr::Fun { name, .. } => {
-                if let Some(x) = defs.get(name) {
-                    x
-                } else {
-                    return Err(ApplyError::NoMatch);
-                }
-            }
-            _ => return Err(ApplyError::Undef),
-        };
-        let v: Vec<_> = f_defs
-            .iter()
-            .filter_map(|x| x.apply(&curr_e).ok().map(move |s| (&x.rep, s)))
-            .collect();
-
-        Ok(Box::new(v.into_iter().flat_map(move |(rep, sub)| {
-            let mut alloc = IdAlloc::new(gen);
-            // apply the same substitution that is applied to the goal in the SLD algorithm.
-            // (see below)
-            let qvars: HashMap<String, Expr> = qvars
-                .iter()
-                .map(|(s, e)| (s.clone(), substitute_and_freshen(&mut alloc, &sub, e)))
-                .collect();
-            let e = e
-                .iter()
-                .chain(rep.iter())
-                .map(|e| substitute_and_freshen(&mut alloc, &sub, e))
-                .collect();
-            apply_internal(gen, defs, e, qvars).unwrap_or_else(|_| Box::new(std::iter::empty()))
-        })) as _)
-    })
-}
-
 // initializes the solution binding set (the set which holds the bindings used in `print_sols`)
 // to maps between variables and themselves. each of them looks like A = A.
 // these are then applied the same substitution that is applied to the goal in the SLD algorithm.
@@ -183,7 +200,8 @@ fn vars<'a>(v: &mut HashMap<String, Expr>, o: &mut Vec<&'a str>, e: &'a [Expr])
     for i in e {
         match i {
             Expr::Fun { args, .. } => vars(v, o, args),
-            Expr::Var { name, .. } if !v.contains_key(name) => {
+            // `_` and `_Name` are not part of the answer
+            Expr::Var { name, .. } if !name.starts_with('_') && !v.contains_key(name) => {
                 v.insert(name.clone(), i.clone());
                 o.push(name)
             }
@@ -192,6 +210,31 @@ fn vars<'a>(v: &mut HashMap<String, Expr>, o: &mut Vec<&'a str>, e: &'a [Expr])
     }
 }
 
+// the variables that occur only once in a clause, which is often a typo.
+// `_` and the variables starting with `_` are meant to be used like that.
+fn singletons(def: &Def) -> Vec<&str> {
+    fn count<'a>(e: &'a Expr, seen: &mut Vec<(&'a str, usize)>) {
+        match e {
+            Expr::Var { name, .. } if !name.starts_with('_') => {
+                match seen.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, c)) => *c += 1,
+                    None => seen.push((name, 1)),
+                }
+            }
+            Expr::Fun { args, .. } => args.iter().for_each(|a| count(a, seen)),
+            _ => {}
+        }
+    }
+    let mut seen = Vec::new();
+    for e in std::iter::once(&def.pat).chain(&def.rep) {
+        count(e, &mut seen);
+    }
+    seen.into_iter()
+        .filter(|(_, c)| *c == 1)
+        .map(|(name, _)| name)
+        .collect()
+}
+
 // avoid stack overflows
 pub fn with_stacker<R>(f: impl FnOnce() -> R) -> R {
     stacker::maybe_grow(32 * 1024, 1024 * 1024, f)
@@ -67,7 +67,7 @@ fn repl(rules: &Rules, ctx: &mut Context) {
             }
         };
 
-        let mut sols_printer = ctx.apply(&rules, &expr);
+        let mut sols_printer = ctx.apply(rules, &expr);
         let mut line = String::new();
 
         'outer: while sols_printer.print_next_sol() {
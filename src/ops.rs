@@ -0,0 +1,219 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::{
+    error::Loc,
+    expr::{Expr, CONS, NIL},
+    unify::ApplyError,
+};
+
+// the kind of an operator. `f` is the operator itself, `x` an argument that
+// has to bind tighter than it, and `y` one that may bind as tight as it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OpType {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+    Xf,
+    Yf,
+}
+
+const OP_TYPES: [(&str, OpType); 7] = [
+    ("xfx", OpType::Xfx),
+    ("xfy", OpType::Xfy),
+    ("yfx", OpType::Yfx),
+    ("fy", OpType::Fy),
+    ("fx", OpType::Fx),
+    ("xf", OpType::Xf),
+    ("yf", OpType::Yf),
+];
+
+impl OpType {
+    // the highest priority the left and right arguments of an operator with priority `p`
+    // can have. prefix operators only have a right one and postfix ones only a left one.
+    pub(crate) fn arg_priorities(self, p: u32) -> (u32, u32) {
+        let below = p.saturating_sub(1);
+        match self {
+            OpType::Xfx => (below, below),
+            OpType::Xfy => (below, p),
+            OpType::Yfx => (p, below),
+            OpType::Fy => (0, p),
+            OpType::Fx => (0, below),
+            OpType::Xf => (below, 0),
+            OpType::Yf => (p, 0),
+        }
+    }
+}
+
+// the operator table, which decides how terms like `a :- b, c` are read and written.
+// a name can be a prefix operator and an infix or postfix one at the same time, like `-`.
+#[derive(Clone, Debug)]
+pub(crate) struct Ops {
+    prefix: HashMap<String, (u32, OpType)>,
+    infix: HashMap<String, (u32, OpType)>,
+    postfix: HashMap<String, (u32, OpType)>,
+}
+
+impl Ops {
+    // the standard ISO operators
+    pub(crate) fn new() -> Self {
+        use OpType::*;
+        let mut ops = Self {
+            prefix: HashMap::new(),
+            infix: HashMap::new(),
+            postfix: HashMap::new(),
+        };
+        let table: [(u32, OpType, &[&str]); 14] = [
+            (1200, Xfx, &[":-", "-->"]),
+            (1200, Fx, &[":-", "?-"]),
+            (1150, Fx, &["dynamic", "table"]),
+            (1100, Xfy, &[";", "|"]),
+            (1050, Xfy, &["->", "*->"]),
+            (1000, Xfy, &[","]),
+            (900, Fy, &["\\+"]),
+            (
+                700,
+                Xfx,
+                &[
+                    "=", "\\=", "==", "\\==", "@<", "@>", "@=<", "@>=", "=..", "is", "=:=", "=\\=",
+                    "<", ">", "=<", ">=",
+                ],
+            ),
+            (600, Xfy, &[":"]),
+            (500, Yfx, &["+", "-", "/\\", "\\/", "xor"]),
+            (400, Yfx, &["*", "/", "//", "rem", "mod", "div", "<<", ">>"]),
+            (200, Xfx, &["**"]),
+            (200, Xfy, &["^"]),
+            (200, Fy, &["-", "+", "\\"]),
+        ];
+        for (p, ty, names) in table {
+            for name in names {
+                ops.add(p, ty, name);
+            }
+        }
+        ops
+    }
+
+    // the operators every term is written with when no other table is at hand
+    pub(crate) fn standard() -> &'static Ops {
+        static STANDARD: OnceLock<Ops> = OnceLock::new();
+        STANDARD.get_or_init(Ops::new)
+    }
+
+    pub(crate) fn prefix(&self, name: &str) -> Option<(u32, OpType)> {
+        self.prefix.get(name).copied()
+    }
+
+    pub(crate) fn infix(&self, name: &str) -> Option<(u32, OpType)> {
+        self.infix.get(name).copied()
+    }
+
+    pub(crate) fn postfix(&self, name: &str) -> Option<(u32, OpType)> {
+        self.postfix.get(name).copied()
+    }
+
+    // the highest priority `name` has as any kind of operator, if it is one
+    pub(crate) fn priority(&self, name: &str) -> Option<u32> {
+        [self.prefix(name), self.infix(name), self.postfix(name)]
+            .into_iter()
+            .flatten()
+            .map(|(p, _)| p)
+            .max()
+    }
+
+    // defines an operator, or removes it if `p` is 0
+    fn add(&mut self, p: u32, ty: OpType, name: &str) {
+        let table = match ty {
+            OpType::Fy | OpType::Fx => &mut self.prefix,
+            OpType::Xfx | OpType::Xfy | OpType::Yfx => &mut self.infix,
+            OpType::Xf | OpType::Yf => &mut self.postfix,
+        };
+        if p == 0 {
+            table.remove(name);
+        } else {
+            table.insert(name.to_string(), (p, ty));
+        }
+    }
+
+    // runs `op(P, Type, Names)`, where `Names` is an atom or a list of atoms
+    pub(crate) fn op(&mut self, p: &Expr, ty: &Expr, names: &Expr) -> Result<(), ApplyError> {
+        let p = match p {
+            Expr::Var { .. } => return Err(ApplyError::Instantiation),
+            Expr::Int { val, .. } if (0..=1200).contains(val) => *val as u32,
+            Expr::Int { .. } => return Err(ApplyError::Domain("operator_priority", p.clone())),
+            _ => return Err(ApplyError::Type("integer", p.clone())),
+        };
+        let ty = match ty {
+            Expr::Var { .. } => return Err(ApplyError::Instantiation),
+            Expr::Fun { name, args, .. } if args.is_empty() => OP_TYPES
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, ty)| *ty)
+                .ok_or_else(|| ApplyError::Domain("operator_specifier", ty.clone()))?,
+            _ => return Err(ApplyError::Type("atom", ty.clone())),
+        };
+
+        let mut list = Vec::new();
+        let mut tail = names;
+        let names = loop {
+            match tail {
+                Expr::Var { .. } => return Err(ApplyError::Instantiation),
+                Expr::Fun { name, args, .. } if name == NIL && args.is_empty() => break list,
+                Expr::Fun { name, args, .. } if name == CONS && args.len() == 2 => {
+                    list.push(&args[0]);
+                    tail = &args[1];
+                }
+                Expr::Fun { args, .. } if args.is_empty() && list.is_empty() => break vec![names],
+                _ => return Err(ApplyError::Type("list", names.clone())),
+            }
+        };
+        // check all the names before changing anything
+        let names = names
+            .into_iter()
+            .map(|name| match name {
+                Expr::Var { .. } => Err(ApplyError::Instantiation),
+                Expr::Fun { name: n, args, .. } if args.is_empty() => {
+                    if n == "," {
+                        return Err(ApplyError::Permission("modify", "operator", name.clone()));
+                    }
+                    // a name can't be both an infix and a postfix operator
+                    let clash = match ty {
+                        OpType::Xfx | OpType::Xfy | OpType::Yfx => self.postfix.contains_key(n),
+                        OpType::Xf | OpType::Yf => self.infix.contains_key(n),
+                        _ => false,
+                    };
+                    if clash && p > 0 {
+                        return Err(ApplyError::Permission("create", "operator", name.clone()));
+                    }
+                    Ok(n.as_str())
+                }
+                _ => Err(ApplyError::Type("atom", name.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for name in names {
+            self.add(p, ty, name);
+        }
+        Ok(())
+    }
+
+    // all the operators, as `op(P, Type, Name)` terms for `current_op/3`
+    pub(crate) fn get_all(&self) -> Vec<(Expr, Expr, Expr)> {
+        let loc = Loc::default();
+        [&self.prefix, &self.infix, &self.postfix]
+            .into_iter()
+            .flatten()
+            .map(|(name, (p, ty))| {
+                let (ty, _) = OP_TYPES.iter().find(|(_, t)| t == ty).unwrap();
+                (
+                    Expr::Int {
+                        val: *p as i64,
+                        loc,
+                    },
+                    Expr::atom(ty, loc),
+                    Expr::atom(name, loc),
+                )
+            })
+            .collect()
+    }
+}
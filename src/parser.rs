@@ -1,80 +1,426 @@
+use std::cell::RefCell;
+
 use crate::{
+    arith::Number,
+    dcg,
     error::{Error, Loc},
-    expr::{Expr, IdAlloc},
-    token::{Scanner, TokenTy},
+    expr::{Expr, IdAlloc, NIL},
+    flags::{DoubleQuotes, Flags},
+    ops::Ops,
+    token::{Scanner, Token, TokenTy},
 };
 
 #[derive(Clone, Debug)]
 pub struct Def {
     pub name: String,
+    pub arity: usize,
     pub loc: Loc,
     pub(crate) pat: Expr,
     pub(crate) rep: Vec<Expr>,
 }
 
+// what a clause in a file can be
+pub(crate) enum Item {
+    Def(Def),
+    // a `:- goal.` directive
+    Directive(Expr),
+}
+
 pub struct Parser<'a> {
     pub(crate) sc: Scanner<'a>,
+    flags: &'a Flags,
+    ops: &'a RefCell<Ops>,
+    // whether double-quoted texts are read as strings even when `double_quotes` is `atom`,
+    // so that a grammar rule can tell them apart from non-terminals
+    keep_strings: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(sc: Scanner<'a>) -> Self {
-        Self { sc }
+    pub(crate) fn new(sc: Scanner<'a>, flags: &'a Flags, ops: &'a RefCell<Ops>) -> Self {
+        Self {
+            sc,
+            flags,
+            ops,
+            keep_strings: false,
+        }
     }
 
-    // parses a horn clause of the form 
-    //     f(args) (:- (expr),+ .)?
-    pub(crate) fn parse_def(&mut self, id: &mut IdAlloc<String>) -> Result<Option<Def>, Error> {
+    // parses a directive of the form
+    //     :- expr .
+    // or a horn clause of the form
+    //     f(args) (:- expr)? .
+    pub(crate) fn parse_item(&mut self, id: &mut IdAlloc<String>) -> Result<Option<Item>, Error> {
         if self.sc.peek()?.ty() == TokenTy::Eof {
             return Ok(None);
         }
-        let (name, loc, pat) = self.parse_expr(id)?;
-        let rep = if self.sc.is_token(TokenTy::Pen)? {
-            self.parse_clause(id)?
-        } else {
-            Vec::new()
-        };
+        self.keep_strings = true;
+        let e = self.parse(id, 1200);
+        self.keep_strings = false;
+        let e = e?;
         self.sc.expect_token(TokenTy::Period)?;
 
-        Ok(Some(Def {
+        // grammar rules are turned into ordinary clauses as they are read
+        let mut e = match e {
+            Expr::Fun {
+                ref name,
+                ref args,
+                loc,
+            } if name == "-->" && args.len() == 2 => match dcg::translate(&args[0], &args[1], id) {
+                Ok(clause) => clause,
+                Err(err) => {
+                    return self
+                        .sc
+                        .syntax_err(loc, format!("invalid grammar rule: {}", err))
+                }
+            },
+            e => e,
+        };
+        // the texts that are not terminals of a grammar rule are atoms after all
+        if self.flags.double_quotes.get() == DoubleQuotes::Atom {
+            e = string_atoms(&e);
+        }
+
+        let (pat, body) = match e {
+            Expr::Fun {
+                ref name,
+                ref mut args,
+                ..
+            } if name == ":-" && args.len() == 1 => {
+                return Ok(Some(Item::Directive(args.pop().unwrap())))
+            }
+            Expr::Fun {
+                ref name,
+                ref mut args,
+                ..
+            } if name == ":-" && args.len() == 2 => {
+                let body = args.pop().unwrap();
+                (args.pop().unwrap(), Some(body))
+            }
+            e => (e, None),
+        };
+        let (name, arity, loc) = match &pat {
+            Expr::Fun { name, args, loc } => (name.clone(), args.len(), *loc),
+            _ => {
+                return self
+                    .sc
+                    .syntax_err(pat.loc(), format!("expected a clause head, found {}", pat))
+            }
+        };
+        let mut rep = Vec::new();
+        if let Some(body) = body {
+            conjuncts(body, &mut rep);
+        }
+
+        Ok(Some(Item::Def(Def {
             name,
+            arity,
             loc,
             pat,
             rep,
-        }))
+        })))
     }
 
-    // parses clauses (expressions separated by commas)
-    // also works for parsing arguments in an expression 
-    // due to the similar shape.
+    // parses a query, which is a conjunction of goals
     pub(crate) fn parse_clause(&mut self, id: &mut IdAlloc<String>) -> Result<Vec<Expr>, Error> {
         let mut v = Vec::new();
+        conjuncts(self.parse(id, 1200)?, &mut v);
+        Ok(v)
+    }
+
+    // parses a term of priority at most `max`.
+    // after a term, an infix or postfix operator continues it as long as it fits in `max`
+    // and the term so far binds tight enough to be its left argument.
+    fn parse(&mut self, id: &mut IdAlloc<String>, max: u32) -> Result<Expr, Error> {
+        self.parse_term(id, max, false)
+    }
+
+    // parses an argument of a compound term or an item of a list
+    fn parse_arg(&mut self, id: &mut IdAlloc<String>) -> Result<Expr, Error> {
+        self.parse_term(id, 999, true)
+    }
+
+    fn parse_term(&mut self, id: &mut IdAlloc<String>, max: u32, arg: bool) -> Result<Expr, Error> {
+        let (mut left, mut prio) = self.parse_primary(id, max, arg)?;
         loop {
-            v.push(self.parse_expr(id)?.2);
-            if !self.sc.is_token(TokenTy::Comma)? {
-                break Ok(v);
+            let tok = self.sc.peek()?;
+            let name = match tok.ty() {
+                TokenTy::Ident(name) | TokenTy::Quoted(name) => name,
+                TokenTy::Comma => ",".to_string(),
+                TokenTy::Bar => "|".to_string(),
+                _ => break,
+            };
+            let loc = left.loc();
+
+            let infix = self.ops.borrow().infix(&name);
+            if let Some((p, ty)) = infix {
+                let (l, r) = ty.arg_priorities(p);
+                if p <= max && prio <= l {
+                    self.sc.next_token()?;
+                    let right = self.parse(id, r)?;
+                    // `a | b` is the same as `a ; b`
+                    let name = if name == "|" { ";".to_string() } else { name };
+                    left = Expr::Fun {
+                        name,
+                        args: vec![left, right],
+                        loc,
+                    };
+                    prio = p;
+                    continue;
+                }
+            }
+            let postfix = self.ops.borrow().postfix(&name);
+            if let Some((p, ty)) = postfix {
+                let (l, _) = ty.arg_priorities(p);
+                if p <= max && prio <= l {
+                    self.sc.next_token()?;
+                    left = Expr::Fun {
+                        name,
+                        args: vec![left],
+                        loc,
+                    };
+                    prio = p;
+                    continue;
+                }
             }
+            break;
         }
+        Ok(left)
     }
 
-    fn parse_expr(&mut self, id: &mut IdAlloc<String>) -> Result<(String, Loc, Expr), Error> {
-        let (loc, name) = self.sc.expect_identifier()?;
-
-        let res = if name.chars().next().unwrap().is_lowercase() {
-            let name = name.clone();
-            let args = if self.sc.is_token(TokenTy::Lparen)? {
-                let args = self.parse_clause(id)?;
+    // parses a term that does not start with an argument of an operator,
+    // giving it with its priority. `arg` tells if it starts an argument.
+    fn parse_primary(
+        &mut self,
+        id: &mut IdAlloc<String>,
+        max: u32,
+        arg: bool,
+    ) -> Result<(Expr, u32), Error> {
+        let tok = self.sc.next_token()?;
+        let loc = tok.loc();
+        let (name, quoted) = match tok.clone().ty() {
+            TokenTy::Lparen => {
+                let e = self.parse(id, 1200)?;
                 self.sc.expect_token(TokenTy::Rparen)?;
-                args
-            } else {
-                Vec::new()
-            };
-            Expr::Fun { name, args, loc }
+                return Ok((e, 0));
+            }
+            TokenTy::Lbracket => return Ok((self.parse_list(id, loc)?, 0)),
+            // `{a, b}` is `'{}'((a, b))`
+            TokenTy::Lbrace => {
+                if self.sc.is_token(TokenTy::Rbrace)? {
+                    return Ok((Expr::atom("{}", loc), 0));
+                }
+                let e = self.parse(id, 1200)?;
+                self.sc.expect_token(TokenTy::Rbrace)?;
+                let name = "{}".to_string();
+                return Ok((
+                    Expr::Fun {
+                        name,
+                        args: vec![e],
+                        loc,
+                    },
+                    0,
+                ));
+            }
+            TokenTy::Str(text) => return Ok((self.string(text, loc), 0)),
+            // every `_` is a different variable
+            TokenTy::Ident(name) if name == "_" => {
+                let id = id.fresh();
+                return Ok((Expr::Var { name, id, loc }, 0));
+            }
+            TokenTy::Ident(name) if name.starts_with(|c: char| c.is_uppercase() || c == '_') => {
+                let id = id.alloc(name.clone());
+                return Ok((Expr::Var { name, id, loc }, 0));
+            }
+            TokenTy::Ident(name) => (name, false),
+            TokenTy::Quoted(name) => (name, true),
+            ty => {
+                return match number(tok, loc, false) {
+                    Some(e) => Ok((e, 0)),
+                    None => self
+                        .sc
+                        .syntax_err(loc, format!("expected a term, found {}", ty)),
+                }
+            }
+        };
+
+        let next = self.sc.peek()?;
+        // a name directly followed by `(` is a compound term, whether it is an operator or not
+        if next.clone().ty() == TokenTy::Lparen && !next.layout() {
+            self.sc.next_token()?;
+            let args = self.parse_args(id)?;
+            return Ok((Expr::Fun { name, args, loc }, 0));
+        }
+        // a `-` directly followed by a number is a negative number
+        if name == "-" && !quoted && !next.layout() {
+            if let Some(e) = number(next.clone(), loc, true) {
+                self.sc.next_token()?;
+                return Ok((e, 0));
+            }
+        }
+
+        // an argument has to be in parentheses if its operator binds looser than 999,
+        // like in `f((dynamic foo))`, so that `f(dynamic foo)` is a syntax error
+        let prefix = self.ops.borrow().prefix(&name);
+        let prefix = prefix.filter(|(p, _)| !(arg && *p > max));
+        if let Some((p, ty)) = prefix.filter(|_| self.is_operand(&next)) {
+            let (_, r) = ty.arg_priorities(p);
+            // elsewhere, an operator that binds looser than allowed, like the one in
+            // `- dynamic foo`, is read as if it were in parentheses
+            let (p, r) = if p > max { (max, r.min(max)) } else { (p, r) };
+            let arg = self.parse(id, r)?;
+            return Ok((
+                Expr::Fun {
+                    name,
+                    args: vec![arg],
+                    loc,
+                },
+                p,
+            ));
+        }
+        Ok((Expr::atom(&name, loc), 0))
+    }
+
+    // tells if the token after a prefix operator starts its argument.
+    // otherwise the operator is just an atom, as in `f(-)` or `- = X`.
+    fn is_operand(&self, next: &Token) -> bool {
+        match next.clone().ty() {
+            TokenTy::Ident(name) | TokenTy::Quoted(name) => {
+                let ops = self.ops.borrow();
+                let infix = ops.infix(&name).is_some() || ops.postfix(&name).is_some();
+                !infix || ops.prefix(&name).is_some()
+            }
+            TokenTy::Rparen
+            | TokenTy::Rbracket
+            | TokenTy::Rbrace
+            | TokenTy::Bar
+            | TokenTy::Comma
+            | TokenTy::Period
+            | TokenTy::Eof => false,
+            _ => true,
+        }
+    }
+
+    // parses the arguments of a compound term after the `(`
+    fn parse_args(&mut self, id: &mut IdAlloc<String>) -> Result<Vec<Expr>, Error> {
+        let mut args = Vec::new();
+        loop {
+            args.push(self.parse_arg(id)?);
+            if !self.sc.is_token(TokenTy::Comma)? {
+                break;
+            }
+        }
+        self.sc.expect_token(TokenTy::Rparen)?;
+        Ok(args)
+    }
+
+    // parses the rest of a list after the `[`.
+    // `[a, b | T]` is sugar for `'.'(a, '.'(b, T))` and `[a, b]` for `[a, b | []]`.
+    fn parse_list(&mut self, id: &mut IdAlloc<String>, loc: Loc) -> Result<Expr, Error> {
+        let nil = Expr::atom(NIL, loc);
+        if self.sc.is_token(TokenTy::Rbracket)? {
+            return Ok(nil);
+        }
+        let mut items = Vec::new();
+        loop {
+            items.push(self.parse_arg(id)?);
+            if !self.sc.is_token(TokenTy::Comma)? {
+                break;
+            }
+        }
+        let tail = if self.sc.is_token(TokenTy::Bar)? {
+            self.parse_arg(id)?
         } else {
-            let name = name.clone();
-            let id = id.alloc(name.clone());
-            Expr::Var { name, id, loc }
+            nil
         };
+        self.sc.expect_token(TokenTy::Rbracket)?;
+        Ok(Expr::list(items, tail))
+    }
+
+    // turns a double-quoted text into the term the `double_quotes` flag asks for
+    fn string(&self, text: String, loc: Loc) -> Expr {
+        let nil = Expr::atom(NIL, loc);
+        match self.flags.double_quotes.get() {
+            DoubleQuotes::Codes => {
+                let codes = text.chars().map(|c| Expr::Int { val: c as i64, loc });
+                Expr::list(codes.collect(), nil)
+            }
+            DoubleQuotes::Chars => {
+                let chars = text
+                    .chars()
+                    .map(|c| Expr::atom(c.encode_utf8(&mut [0; 4]), loc));
+                Expr::list(chars.collect(), nil)
+            }
+            DoubleQuotes::Atom if !self.keep_strings => Expr::atom(&text, loc),
+            DoubleQuotes::Atom | DoubleQuotes::String => Expr::Str { val: text, loc },
+        }
+    }
+}
+
+// turns the strings in `e` into atoms
+fn string_atoms(e: &Expr) -> Expr {
+    crate::with_stacker(|| match e {
+        Expr::Str { val, loc } => Expr::atom(val, *loc),
+        Expr::Fun { name, args, loc } => Expr::Fun {
+            name: name.clone(),
+            args: args.iter().map(string_atoms).collect(),
+            loc: *loc,
+        },
+        _ => e.clone(),
+    })
+}
 
-        Ok((name, loc, res))
+// splits a conjunction like `a, (b, c)` into its goals.
+// a variable goal `G` is run as `call(G)`, so that a cut it is bound to stays local.
+pub(crate) fn conjuncts(mut e: Expr, v: &mut Vec<Expr>) {
+    match e {
+        Expr::Fun {
+            ref name,
+            ref mut args,
+            ..
+        } if name == "," && args.len() == 2 => {
+            let [a, b]: [Expr; 2] = std::mem::take(args).try_into().unwrap();
+            conjuncts(a, v);
+            conjuncts(b, v);
+        }
+        Expr::Var { loc, .. } => v.push(Expr::Fun {
+            name: "call".to_string(),
+            args: vec![e],
+            loc,
+        }),
+        e => v.push(e),
+    }
+}
+
+// reads a text that is just a number, like `" -42"` or `"0'a"`, as `number_codes/2` does
+pub(crate) fn parse_number(text: &str) -> Option<Expr> {
+    let text = text.trim_start();
+    let (neg, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let mut sc = Scanner::new(text);
+    let tok = sc.next_token().ok()?;
+    // no layout can come between the minus and the number
+    if neg && tok.layout() {
+        return None;
     }
+    let e = number(tok, Loc::default(), neg)?;
+    (sc.next_token().ok()?.ty() == TokenTy::Eof).then_some(e)
+}
+
+// turns a number token into an expression, negating it if it comes after a `-`
+fn number(tok: Token, loc: Loc, neg: bool) -> Option<Expr> {
+    Some(match tok.ty() {
+        TokenTy::Int(val) => Expr::Int {
+            val: if neg { -val } else { val },
+            loc,
+        },
+        // `-9223372036854775808` is too big before it is negated, but not after
+        TokenTy::BigInt(val) => Number::from(if neg { -val } else { val }).into_expr(loc),
+        TokenTy::Float(val) => Expr::Float {
+            val: if neg { -val } else { val },
+            loc,
+        },
+        _ => return None,
+    })
 }
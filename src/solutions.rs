@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+
+use crate::{
+    arith::{self, Number},
+    builtins::{unify, Subs},
+    error::Loc,
+    expr::{Expr, NIL},
+    solve::Solver,
+    terms::is_partial_list,
+    unify::{substitute, unify_exprs, variant, ApplyError},
+};
+
+// runs the all-solutions predicates, which collect the solutions of a goal
+// in a nested resolution. returns `None` for any other goal.
+pub(crate) fn call(solver: &mut Solver, e: &Expr) -> Option<Result<Subs, ApplyError>> {
+    let (name, args) = match e {
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        _ => return None,
+    };
+    let loc = e.loc();
+    Some(match (name, args) {
+        ("findall", [t, g, l]) => findall(solver, t, g, l)
+            .and_then(|sols| unify(l, &Expr::list(sols, nil(loc)), solver.occurs_check())),
+        ("bagof", [t, g, l]) => bagof(solver, t, g, l, false),
+        ("setof", [t, g, l]) => bagof(solver, t, g, l, true),
+        ("aggregate_all", [spec, g, r]) => aggregate_all(solver, spec, g, r),
+        _ => return None,
+    })
+}
+
+// the copies of `t` for every solution of `g`, in order.
+// `l` is where they will go, so it has to be a list or a partial list.
+fn findall(solver: &mut Solver, t: &Expr, g: &Expr, l: &Expr) -> Result<Vec<Expr>, ApplyError> {
+    if !is_partial_list(l) {
+        return Err(ApplyError::Type("list", l.clone()));
+    }
+    match g {
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Fun { .. } => solver.solve_all(t, g),
+        _ => Err(ApplyError::Type("callable", g.clone())),
+    }
+}
+
+// `bagof/3` and `setof/3`. the solutions are grouped by the bindings of the variables
+// that are free in the goal, and every group is one solution.
+// in `V^G`, the variables of `V` are not free, so they are not grouped by.
+// `setof/3` sorts the groups and the solutions in them, and removes duplicates.
+fn bagof(solver: &mut Solver, t: &Expr, g: &Expr, l: &Expr, set: bool) -> Result<Subs, ApplyError> {
+    let mut bound = Vec::new();
+    vars(t, &mut bound);
+    let mut goal = g;
+    while let Expr::Fun { name, args, .. } = goal {
+        if name != "^" || args.len() != 2 {
+            break;
+        }
+        vars(&args[0], &mut bound);
+        goal = &args[1];
+    }
+    let mut free = Vec::new();
+    vars(goal, &mut free);
+    free.retain(|v| !bound.contains(v));
+
+    let loc = g.loc();
+    let witness = Expr::list(free, nil(loc));
+    let sols = findall(solver, &pair(witness.clone(), t.clone()), goal, l)?;
+    let occurs = solver.occurs_check();
+
+    // the witnesses of a group are the same up to their variables
+    let mut groups: Vec<(Vec<Expr>, Vec<Expr>)> = Vec::new();
+    for mut sol in sols {
+        let [w, t]: [Expr; 2] = match sol {
+            Expr::Fun { ref mut args, .. } => std::mem::take(args).try_into().unwrap(),
+            _ => unreachable!(),
+        };
+        match groups.iter_mut().find(|(ws, _)| variant(&ws[0], &w)) {
+            Some((ws, ts)) => {
+                ws.push(w);
+                ts.push(t);
+            }
+            None => groups.push((vec![w], vec![t])),
+        }
+    }
+    if set {
+        groups.sort_by(|(a, _), (b, _)| a[0].compare(&b[0]));
+    }
+
+    groups
+        .into_iter()
+        .map(|(ws, ts)| {
+            // the free variables are bound to the witness, which makes
+            // the variables of all the witnesses in the group the same ones
+            let same = vec![witness.clone(); ws.len()];
+            let (same, ws) = (Expr::list(same, nil(loc)), Expr::list(ws, nil(loc)));
+            let mut sub = unify_exprs(&same, &ws, occurs)?;
+            let mut ts: Vec<_> = ts.iter().map(|t| substitute(&sub, t)).collect();
+            if set {
+                ts.sort_by(Expr::compare);
+                ts.dedup_by(|a, b| a.compare(b).is_eq());
+            }
+            sub.extend(unify_exprs(
+                &substitute(&sub, l),
+                &Expr::list(ts, nil(loc)),
+                occurs,
+            )?);
+            Ok(sub)
+        })
+        .filter(|sub| !matches!(sub, Err(ApplyError::UnifyFail)))
+        .collect()
+}
+
+// `aggregate_all(Spec, Goal, Result)`, where `Spec` is one of
+// `count`, `sum(E)`, `max(E)`, `min(E)`, `max(E, W)`, `min(E, W)`, `bag(T)` or `set(T)`
+fn aggregate_all(solver: &mut Solver, spec: &Expr, g: &Expr, r: &Expr) -> Result<Subs, ApplyError> {
+    let loc = spec.loc();
+    // a template for the answers that are only counted or thrown away
+    let any = solver.var(loc);
+    let (name, args) = match spec {
+        Expr::Var { .. } => return Err(ApplyError::Instantiation),
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        _ => return Err(ApplyError::Domain("aggregate_spec", spec.clone())),
+    };
+    match (name, args) {
+        ("count", []) => {
+            let n = findall(solver, &any, g, &any)?.len();
+            unify(r, &Expr::Int { val: n as i64, loc }, solver.occurs_check())
+        }
+        ("sum", [e]) => {
+            let mut sum = Number::Int(0);
+            for val in findall(solver, e, g, &any)? {
+                let args = vec![sum.into_expr(loc), val];
+                sum = arith::eval(&Expr::Fun {
+                    name: "+".to_string(),
+                    args,
+                    loc,
+                })?;
+            }
+            unify(r, &sum.into_expr(loc), solver.occurs_check())
+        }
+        ("max" | "min", [e]) => {
+            let vals = findall(solver, e, g, &any)?;
+            match best(name, vals.iter().map(|v| (v, &any))) {
+                Some((val, _)) => unify(r, val, solver.occurs_check()),
+                None => Ok(Vec::new()),
+            }
+        }
+        // the witness `W` tells where the best value comes from
+        ("max" | "min", [e, w]) => {
+            let sols = findall(solver, &pair(e.clone(), w.clone()), g, &any)?;
+            let sols = sols.iter().map(|sol| match sol {
+                Expr::Fun { args, .. } => (&args[0], &args[1]),
+                _ => unreachable!(),
+            });
+            match best(name, sols) {
+                Some((val, w)) => {
+                    let args = vec![val.clone(), w.clone()];
+                    let name = name.to_string();
+                    unify(r, &Expr::Fun { name, args, loc }, solver.occurs_check())
+                }
+                None => Ok(Vec::new()),
+            }
+        }
+        ("bag", [t]) => {
+            let sols = findall(solver, t, g, &any)?;
+            unify(r, &Expr::list(sols, nil(loc)), solver.occurs_check())
+        }
+        ("set", [t]) => {
+            let mut sols = findall(solver, t, g, &any)?;
+            sols.sort_by(Expr::compare);
+            sols.dedup_by(|a, b| a.compare(b).is_eq());
+            unify(r, &Expr::list(sols, nil(loc)), solver.occurs_check())
+        }
+        _ => Err(ApplyError::Domain("aggregate_spec", spec.clone())),
+    }
+}
+
+// the greatest value for `max`, or the smallest one for `min`, in the standard order of terms,
+// with its witness. the first one wins among equal values.
+fn best<'a>(
+    name: &str,
+    sols: impl Iterator<Item = (&'a Expr, &'a Expr)>,
+) -> Option<(&'a Expr, &'a Expr)> {
+    let better = if name == "max" {
+        Ordering::Greater
+    } else {
+        Ordering::Less
+    };
+    let mut best: Option<(&Expr, &Expr)> = None;
+    for (val, w) in sols {
+        if best.is_none_or(|(b, _)| val.compare(b) == better) {
+            best = Some((val, w));
+        }
+    }
+    best
+}
+
+// the variables in `e` that are not in `v` yet, in the order they appear
+fn vars(e: &Expr, v: &mut Vec<Expr>) {
+    crate::with_stacker(|| match e {
+        Expr::Var { .. } if !v.contains(e) => v.push(e.clone()),
+        Expr::Fun { args, .. } => args.iter().for_each(|a| vars(a, v)),
+        _ => {}
+    })
+}
+
+fn pair(a: Expr, b: Expr) -> Expr {
+    let loc = a.loc();
+    Expr::Fun {
+        name: "-".to_string(),
+        args: vec![a, b],
+        loc,
+    }
+}
+
+fn nil(loc: Loc) -> Expr {
+    Expr::atom(NIL, loc)
+}
@@ -0,0 +1,637 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, vec};
+
+use crate::{
+    atoms, builtins,
+    db::{self, Clauses},
+    dcg,
+    error::Loc,
+    expr::{Expr, IdAlloc, NIL},
+    flags::OccursCheck,
+    parser::Def,
+    solutions,
+    tabling::{self, Tables},
+    terms::{self, is_partial_list},
+    unify::{freshen, substitute, unify_exprs, ApplyError},
+    Context, Rules,
+};
+
+// a goal still to be solved
+#[derive(Clone)]
+enum Goal {
+    // an ordinary goal. a `!` in it cuts the choicepoint stack back to `cut` choicepoints.
+    Call { e: Expr, cut: usize },
+    // cuts the choicepoint stack back to the given height,
+    // which commits to the first solution of something like the goal of `\+`
+    CutTo(usize),
+    // drops the else branch of `*->`, which is the choicepoint at the given height
+    SoftCut(usize),
+    // leaves the goal of the `catch/3` whose choicepoint is at the given height
+    ExitCatch(usize),
+    Fail,
+    // throws a ball, as raised by the goal before it
+    Throw(Expr),
+}
+
+// a clause whose head unified with a goal: its position, the bindings and its body
+type Match = (usize, HashMap<u64, Expr>, Vec<Expr>);
+
+// a state the solver can go back to when the current branch fails
+struct ChoicePoint {
+    // the goals and the answer as they were when the choicepoint was made
+    goals: Vec<Goal>,
+    qvars: HashMap<String, Expr>,
+    alt: Alt,
+}
+
+// what to try next when going back to a choicepoint
+enum Alt {
+    // the clauses of a predicate starting at `next`, that still have to be tried for `goal`
+    Clauses {
+        goal: Expr,
+        clauses: Clauses,
+        next: usize,
+    },
+    // the clauses `retract(clause)` can still remove, starting at `next`
+    Retract {
+        clause: Expr,
+        clauses: Clauses,
+        next: usize,
+    },
+    // the other answers of a built-in predicate
+    Answers(vec::IntoIter<HashMap<u64, Expr>>),
+    // goals to run instead of the ones that failed
+    Goals(Vec<Goal>),
+    // nothing is left to try, like in the else branch of `*->` once the condition succeeded
+    Spent,
+    // a `catch/3` that is running its goal. it only catches a ball that unifies with `catcher`,
+    // and then goes back to the state it was called in to run `recovery`.
+    Catch {
+        catcher: Expr,
+        recovery: Expr,
+    },
+}
+
+// the selection + SLD algorithm + backtracking.
+// the solutions are found one at a time, going back to the latest choicepoint for the next one.
+pub(crate) struct Solver<'a> {
+    defs: &'a Rules,
+    ctx: &'a Context,
+    // the goals, with the next one to be solved at the end
+    goals: Vec<Goal>,
+    // the bindings of the variables in the query, which make up the answer
+    qvars: HashMap<String, Expr>,
+    stack: Vec<ChoicePoint>,
+    // gives the variables of every clause that is used new ids
+    gen: IdAlloc<u64>,
+    // whether the last solution was given, so the next one has to be looked for by backtracking
+    backtrack: bool,
+    // the answers of the calls to tabled predicates, shared with the nested solvers
+    tables: Rc<RefCell<Tables>>,
+}
+
+impl<'a> Solver<'a> {
+    // solves the goals, which are in their order in the source
+    pub(crate) fn new(
+        defs: &'a Rules,
+        ctx: &'a Context,
+        goals: Vec<Expr>,
+        qvars: HashMap<String, Expr>,
+    ) -> Self {
+        Self {
+            defs,
+            ctx,
+            goals: goals
+                .into_iter()
+                .rev()
+                .map(|e| Goal::Call { e, cut: 0 })
+                .collect(),
+            qvars,
+            stack: Vec::new(),
+            gen: IdAlloc::new(ctx.id.get_next()),
+            backtrack: false,
+            tables: Rc::default(),
+        }
+    }
+
+    // solves the goal `e`, telling if it succeeded
+    fn step(&mut self, e: &Expr, cut: usize) -> Result<bool, ApplyError> {
+        let (name, args) = match e {
+            Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+            Expr::Var { .. } => return Err(ApplyError::Instantiation),
+            _ => return Err(ApplyError::Type("callable", e.clone())),
+        };
+        match (name, args) {
+            // `call(G, A1, ..., An)` calls `G` with the extra arguments `A1, ..., An`.
+            // a cut inside `G` is local to it.
+            ("call", [g, extra @ ..]) if extra.len() < 8 => {
+                let e = match g {
+                    Expr::Var { .. } => return Err(ApplyError::Instantiation),
+                    Expr::Fun { name, args, loc } => Expr::Fun {
+                        name: name.clone(),
+                        args: args.iter().chain(extra).cloned().collect(),
+                        loc: *loc,
+                    },
+                    _ => return Err(ApplyError::Type("callable", g.clone())),
+                };
+                let cut = self.stack.len();
+                self.goals.push(Goal::Call { e, cut });
+                return Ok(true);
+            }
+            // `phrase(G, L, R)` parses the list `L` with the grammar body `G`, leaving `R`.
+            // `phrase(G, L)` has to parse all of `L`.
+            ("phrase", [g, l, rest @ ..]) if rest.len() < 2 => {
+                let r = match rest {
+                    [r] => r.clone(),
+                    _ => Expr::atom(NIL, l.loc()),
+                };
+                match g {
+                    Expr::Var { .. } => return Err(ApplyError::Instantiation),
+                    Expr::Fun { .. } => {}
+                    _ => return Err(ApplyError::Type("callable", g.clone())),
+                }
+                for l in [l, &r] {
+                    if !is_partial_list(l) {
+                        return Err(ApplyError::Type("list", l.clone()));
+                    }
+                }
+                let e = dcg::body_goal(g, l.clone(), r, &mut self.gen)?;
+                let cut = self.stack.len();
+                self.goals.push(Goal::Call { e, cut });
+                return Ok(true);
+            }
+            ("!", []) => {
+                self.stack.truncate(cut);
+                return Ok(true);
+            }
+            // a conjunction that is not flattened at load time, like the one in `\+ (a, b)`
+            (",", [a, b]) => {
+                self.goals.push(Goal::Call { e: b.clone(), cut });
+                self.goals.push(Goal::Call { e: a.clone(), cut });
+                return Ok(true);
+            }
+            // negation as failure: if the goal has a solution, it is cut away and the negation
+            // fails. otherwise the solver comes back to the choicepoint, where the negation holds.
+            // a cut inside the goal is local to it.
+            ("\\+" | "not", [g]) => {
+                let h = self.stack.len();
+                self.push(Alt::Goals(Vec::new()));
+                self.goals.push(Goal::Fail);
+                self.goals.push(Goal::CutTo(h));
+                self.goals.push(Goal::Call {
+                    e: g.clone(),
+                    cut: h + 1,
+                });
+                return Ok(true);
+            }
+            // outside of `bagof/3` and `setof/3`, `V^G` just calls `G`
+            ("^", [_, g]) => {
+                let cut = self.stack.len();
+                self.goals.push(Goal::Call { e: g.clone(), cut });
+                return Ok(true);
+            }
+            // the goal is run as with `call/1`, and so is the recovery,
+            // once the goal throws a ball that unifies with the catcher
+            ("catch", [g, catcher, recovery]) => {
+                let h = self.stack.len();
+                self.push(Alt::Catch {
+                    catcher: catcher.clone(),
+                    recovery: recovery.clone(),
+                });
+                self.goals.push(Goal::ExitCatch(h));
+                self.goals.push(Goal::Call {
+                    e: g.clone(),
+                    cut: h + 1,
+                });
+                return Ok(true);
+            }
+            // the ball is copied, so the bindings undone while unwinding don't change it
+            ("throw", [ball]) => {
+                if let Expr::Var { .. } = ball {
+                    return Err(ApplyError::Instantiation);
+                }
+                self.gen.new_clause();
+                return Err(ApplyError::Thrown(freshen(ball, &mut self.gen)));
+            }
+            ("true", []) => return Ok(true),
+            ("fail" | "false", []) => return Ok(false),
+            // if-then-else commits to the first solution of the condition, and only runs the
+            // else branch if it has none. with `*->`, all the solutions of the condition are
+            // kept, but the else branch is still dropped after the first one.
+            (";", [l, r]) => {
+                let h = self.stack.len();
+                self.push(Alt::Goals(vec![Goal::Call { e: r.clone(), cut }]));
+                match is_ite(l) {
+                    Some((soft, c, then)) => {
+                        self.goals.push(Goal::Call {
+                            e: then.clone(),
+                            cut,
+                        });
+                        self.goals.push(if soft {
+                            Goal::SoftCut(h)
+                        } else {
+                            Goal::CutTo(h)
+                        });
+                        self.goals.push(Goal::Call {
+                            e: c.clone(),
+                            cut: h + 1,
+                        });
+                    }
+                    // the branches of a plain disjunction are transparent to cut
+                    None => self.goals.push(Goal::Call { e: l.clone(), cut }),
+                }
+                return Ok(true);
+            }
+            // without an else branch, the else branch fails
+            ("->" | "*->", [c, then]) => {
+                let h = self.stack.len();
+                self.goals.push(Goal::Call {
+                    e: then.clone(),
+                    cut,
+                });
+                if name == "->" {
+                    self.goals.push(Goal::CutTo(h));
+                }
+                self.goals.push(Goal::Call {
+                    e: c.clone(),
+                    cut: h,
+                });
+                return Ok(true);
+            }
+            _ => {}
+        }
+
+        // retracting a clause can be retried to retract the next one that matches
+        if let ("retract", [c]) = (name, args) {
+            let Some(clauses) = self.defs.retracting(c)? else {
+                return Ok(false);
+            };
+            let Some((i, sub)) = self.defs.retract(
+                c,
+                &clauses,
+                0,
+                &mut self.gen,
+                self.ctx.flags.occurs_check.get(),
+            ) else {
+                return Ok(false);
+            };
+            if i + 1 < clauses.len() {
+                self.push(Alt::Retract {
+                    clause: c.clone(),
+                    clauses,
+                    next: i + 1,
+                });
+            }
+            self.resume(&sub, Vec::new(), cut);
+            return Ok(true);
+        }
+
+        // built-in predicates don't have a body, they only bind variables
+        let occurs = self.occurs_check();
+        let subs = solutions::call(self, e)
+            .or_else(|| db::call(self.defs, e, &mut self.gen, occurs))
+            .or_else(|| terms::call(e, &mut self.gen, occurs))
+            .or_else(|| atoms::call(e, occurs))
+            .or_else(|| builtins::call(e, self.ctx))
+            .or_else(|| tabling::call(self, e));
+        if let Some(subs) = subs {
+            let mut subs = subs?.into_iter();
+            let Some(sub) = subs.next() else {
+                return Ok(false);
+            };
+            if subs.len() > 0 {
+                self.push(Alt::Answers(subs));
+            }
+            self.resume(&sub, Vec::new(), cut);
+            return Ok(true);
+        }
+
+        self.resolve(e)
+    }
+
+    // solves the goal `e` with the clauses of its predicate, telling if one of them matched
+    fn resolve(&mut self, e: &Expr) -> Result<bool, ApplyError> {
+        let Expr::Fun { name, args, .. } = e else {
+            unreachable!()
+        };
+        let clauses = match self.defs.get(name, args.len()) {
+            Some(x) => x,
+            None => return Err(ApplyError::NoMatch),
+        };
+        // the choicepoint for the other clauses, if there is one, is right above the cut barrier
+        let h = self.stack.len();
+        let Some((i, sub, body)) = self.next_clause(e, &clauses, 0)? else {
+            return Ok(false);
+        };
+        if i + 1 < clauses.len() {
+            self.push(Alt::Clauses {
+                goal: e.clone(),
+                clauses,
+                next: i + 1,
+            });
+        }
+        self.resume(&sub, body, h);
+        Ok(true)
+    }
+
+    // all the solutions of `goal` in a nested resolution, as copies of `template`.
+    // every copy gets its own fresh variables.
+    pub(crate) fn solve_all(
+        &mut self,
+        template: &Expr,
+        goal: &Expr,
+    ) -> Result<Vec<Expr>, ApplyError> {
+        self.solve_nested(template, |sub| {
+            sub.goals.push(Goal::Call {
+                e: goal.clone(),
+                cut: 0,
+            });
+            Ok(true)
+        })
+    }
+
+    // all the solutions of `goal` from the clauses of its predicate, even if it is tabled,
+    // as copies of `goal`
+    pub(crate) fn solve_clauses(&mut self, goal: &Expr) -> Result<Vec<Expr>, ApplyError> {
+        self.solve_nested(goal, |sub| sub.resolve(goal))
+    }
+
+    // the solutions of a nested resolution that `start` sets up, as copies of `template`
+    fn solve_nested(
+        &mut self,
+        template: &Expr,
+        start: impl FnOnce(&mut Solver<'a>) -> Result<bool, ApplyError>,
+    ) -> Result<Vec<Expr>, ApplyError> {
+        let qvars = HashMap::from([(String::new(), template.clone())]);
+        let mut sub = Solver::new(self.defs, self.ctx, Vec::new(), qvars);
+        // the nested solver has to use ids that are not used here yet, and the other way round
+        sub.gen = IdAlloc::new(self.gen.get_next());
+        sub.tables = self.tables.clone();
+        let started = start(&mut sub);
+        let sols: Result<Vec<_>, _> = match started {
+            Ok(true) => sub
+                .by_ref()
+                .map(|sol| sol.map(|mut s| s.remove("").unwrap()))
+                .collect(),
+            _ => Ok(Vec::new()),
+        };
+        self.gen = IdAlloc::new(sub.gen.get_next());
+        started?;
+        // a ball thrown by the goal goes on from here
+        let sols = sols.map_err(ApplyError::Thrown)?;
+        Ok(sols.iter().map(|e| self.copy(e)).collect())
+    }
+
+    // a copy of `e` with fresh variables
+    pub(crate) fn copy(&mut self, e: &Expr) -> Expr {
+        self.gen.new_clause();
+        freshen(e, &mut self.gen)
+    }
+
+    // a new variable
+    pub(crate) fn var(&mut self, loc: Loc) -> Expr {
+        let id = self.gen.fresh();
+        Expr::Var {
+            name: format!("_G{}", id),
+            id,
+            loc,
+        }
+    }
+
+    pub(crate) fn defs(&self) -> &'a Rules {
+        self.defs
+    }
+
+    pub(crate) fn tables(&self) -> Rc<RefCell<Tables>> {
+        self.tables.clone()
+    }
+
+    // what unifications do when a variable would be bound to a term that contains it
+    pub(crate) fn occurs_check(&self) -> OccursCheck {
+        self.ctx.flags.occurs_check.get()
+    }
+
+    // finds the first clause starting at `from` whose head unifies with the goal
+    fn next_clause(
+        &mut self,
+        goal: &Expr,
+        clauses: &[Rc<Def>],
+        from: usize,
+    ) -> Result<Option<Match>, ApplyError> {
+        let occurs = self.occurs_check();
+        for (i, def) in clauses.iter().enumerate().skip(from) {
+            match def.apply(goal, &mut self.gen, occurs) {
+                Ok((sub, body)) => return Ok(Some((i, sub, body))),
+                Err(ApplyError::UnifyFail) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+
+    // makes a choicepoint for the current state
+    fn push(&mut self, alt: Alt) {
+        self.stack.push(ChoicePoint {
+            goals: self.goals.clone(),
+            qvars: self.qvars.clone(),
+            alt,
+        });
+    }
+
+    // goes on with the bindings `sub` and the goals of a clause body,
+    // which is reversed like the rest of the goals.
+    // a cut in the body cuts the stack back to `cut`.
+    fn resume(&mut self, sub: &HashMap<u64, Expr>, body: Vec<Expr>, cut: usize) {
+        // apply the same substitution that is applied to the goal in the SLD algorithm
+        if !sub.is_empty() {
+            for goal in &mut self.goals {
+                if let Goal::Call { e, .. } = goal {
+                    *e = substitute(sub, e);
+                }
+            }
+            for e in self.qvars.values_mut() {
+                *e = substitute(sub, e);
+            }
+        }
+        self.goals.extend(body.into_iter().map(|e| Goal::Call {
+            e: substitute(sub, &e),
+            cut,
+        }));
+    }
+
+    // goes back to the latest choicepoint that still has something to try,
+    // telling if there was one
+    fn backtrack(&mut self) -> bool {
+        while let Some(cp) = self.stack.pop() {
+            // a cut in a clause tried here cuts this choicepoint too
+            let h = self.stack.len();
+            let ChoicePoint { goals, qvars, alt } = cp;
+            match alt {
+                Alt::Clauses {
+                    goal,
+                    clauses,
+                    next,
+                } => {
+                    let (i, sub, body) = match self.next_clause(&goal, &clauses, next) {
+                        Ok(Some(found)) => found,
+                        Ok(None) => continue,
+                        // the error is raised from the state the clause was tried in
+                        Err(err) => {
+                            self.restore(goals, qvars, None);
+                            self.goals.push(Goal::Throw(err.into_ball(&goal)));
+                            return true;
+                        }
+                    };
+                    self.restore(
+                        goals,
+                        qvars,
+                        (i + 1 < clauses.len()).then_some(Alt::Clauses {
+                            goal,
+                            clauses,
+                            next: i + 1,
+                        }),
+                    );
+                    self.resume(&sub, body, h);
+                }
+                Alt::Retract {
+                    clause,
+                    clauses,
+                    next,
+                } => {
+                    let Some((i, sub)) = self.defs.retract(
+                        &clause,
+                        &clauses,
+                        next,
+                        &mut self.gen,
+                        self.ctx.flags.occurs_check.get(),
+                    ) else {
+                        continue;
+                    };
+                    self.restore(
+                        goals,
+                        qvars,
+                        (i + 1 < clauses.len()).then_some(Alt::Retract {
+                            clause,
+                            clauses,
+                            next: i + 1,
+                        }),
+                    );
+                    self.resume(&sub, Vec::new(), h);
+                }
+                Alt::Answers(mut subs) => {
+                    let Some(sub) = subs.next() else {
+                        continue;
+                    };
+                    self.restore(goals, qvars, (subs.len() > 0).then_some(Alt::Answers(subs)));
+                    self.resume(&sub, Vec::new(), h);
+                }
+                Alt::Goals(alt) => {
+                    self.restore(goals, qvars, None);
+                    self.goals.extend(alt);
+                }
+                Alt::Spent | Alt::Catch { .. } => continue,
+            }
+            return true;
+        }
+        false
+    }
+
+    // unwinds the choicepoint stack to the latest `catch/3` that is still running its goal
+    // and whose catcher unifies with the ball, and runs its recovery.
+    // gives the ball back if nothing catches it.
+    fn throw(&mut self, ball: Expr) -> Result<(), Expr> {
+        while let Some(cp) = self.stack.pop() {
+            let h = self.stack.len();
+            let ChoicePoint { goals, qvars, alt } = cp;
+            let Alt::Catch { catcher, recovery } = alt else {
+                continue;
+            };
+            // the goal of the catch has already exited if nothing is left to leave it
+            let running = self
+                .goals
+                .iter()
+                .any(|g| matches!(g, Goal::ExitCatch(i) if *i == h));
+            if !running {
+                continue;
+            }
+            let Ok(sub) = unify_exprs(&catcher, &ball, self.occurs_check()) else {
+                continue;
+            };
+            self.restore(goals, qvars, None);
+            self.resume(&sub, vec![recovery], h);
+            return Ok(());
+        }
+        Err(ball)
+    }
+
+    // goes back to the state of a choicepoint, keeping it if it has more to try
+    fn restore(&mut self, goals: Vec<Goal>, qvars: HashMap<String, Expr>, alt: Option<Alt>) {
+        self.goals = goals;
+        self.qvars = qvars;
+        if let Some(alt) = alt {
+            self.push(alt);
+        }
+    }
+}
+
+// the answers are the bindings of the variables of the query,
+// until a ball is thrown that nothing catches
+impl Iterator for Solver<'_> {
+    type Item = Result<HashMap<String, Expr>, Expr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.backtrack && !self.backtrack() {
+            return None;
+        }
+        self.backtrack = true;
+        loop {
+            let succeeded = match self.goals.pop() {
+                None => return Some(Ok(self.qvars.clone())),
+                Some(Goal::Call { e, cut }) => match self.step(&e, cut) {
+                    Ok(succeeded) => succeeded,
+                    Err(ApplyError::UnifyFail) => false,
+                    Err(err) => {
+                        self.goals.push(Goal::Throw(err.into_ball(&e)));
+                        true
+                    }
+                },
+                Some(Goal::Throw(ball)) => match self.throw(ball) {
+                    Ok(()) => true,
+                    Err(ball) => {
+                        self.stack.clear();
+                        return Some(Err(ball));
+                    }
+                },
+                Some(Goal::CutTo(h)) => {
+                    self.stack.truncate(h);
+                    true
+                }
+                Some(Goal::SoftCut(h)) => {
+                    self.stack[h].alt = Alt::Spent;
+                    true
+                }
+                // the catch is not needed anymore if its goal left no choicepoints
+                Some(Goal::ExitCatch(h)) => {
+                    if self.stack.len() == h + 1 {
+                        self.stack.pop();
+                    }
+                    true
+                }
+                Some(Goal::Fail) => false,
+            };
+            if !succeeded && !self.backtrack() {
+                return None;
+            }
+        }
+    }
+}
+
+// the condition and the then branch of `C -> T` or `C *-> T`,
+// and whether it is the latter
+fn is_ite(e: &Expr) -> Option<(bool, &Expr, &Expr)> {
+    match e {
+        Expr::Fun { name, args, .. } if args.len() == 2 && (name == "->" || name == "*->") => {
+            Some((name == "*->", &args[0], &args[1]))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,228 @@
+use std::cell::RefCell;
+
+use crate::{
+    builtins::{unify, Subs},
+    expr::Expr,
+    solve::Solver,
+    unify::{variant, ApplyError},
+};
+
+// how an argument of a tabled predicate is tabled
+#[derive(Clone)]
+pub(crate) enum Mode {
+    // answers that differ in it are kept apart
+    Index,
+    // of the answers that only differ in the other arguments, only the one where it is
+    // the least, or the greatest, in the standard order is kept
+    Min,
+    Max,
+    // the answers that only differ in the other arguments are joined into one
+    // by the predicate `Name/3`, as in `Name(Old, New, Joined)`
+    Lattice(String),
+}
+
+// the answers found so far for a call to a tabled predicate
+struct Table {
+    call: Expr,
+    answers: Vec<Expr>,
+    // whether all the answers are in, so the clauses don't have to be run again
+    complete: bool,
+}
+
+// a tabled call that is being evaluated
+struct Frame {
+    table: usize,
+    // the lowest frame whose call it used while that call was still being evaluated
+    low: usize,
+    // how many tables were waiting to be completed when it started
+    pending: usize,
+}
+
+// the tables of the calls to tabled predicates made by a query.
+// this is linear tabling rather than SLG resolution: a call that is already being evaluated
+// is not suspended until more answers come up, it only gets the answers found so far.
+// instead, the first call of a group of calls that use each other runs its clauses
+// again and again until no table changes, and then all of the group is complete.
+// this way left and mutual recursion terminate, at the cost of running clauses again.
+#[derive(Default)]
+pub(crate) struct Tables {
+    tables: Vec<Table>,
+    stack: Vec<Frame>,
+    // the tables that were evaluated, but used a call that is still being evaluated,
+    // so they can only be completed along with it
+    pending: Vec<usize>,
+    // how many answers were added to any table
+    added: usize,
+}
+
+// runs a call to a tabled predicate, with its answers as the solutions.
+// returns `None` for any other goal.
+pub(crate) fn call(solver: &mut Solver, e: &Expr) -> Option<Result<Subs, ApplyError>> {
+    let Expr::Fun { name, args, loc } = e else {
+        return None;
+    };
+    let modes = solver.defs().table(name, args.len())?;
+    // the arguments that are not indexed are left open in the table's call,
+    // so that only the best answers are checked against them
+    let call = Expr::Fun {
+        name: name.clone(),
+        args: args
+            .iter()
+            .zip(&modes)
+            .map(|(arg, mode)| match mode {
+                Mode::Index => arg.clone(),
+                _ => solver.var(arg.loc()),
+            })
+            .collect(),
+        loc: *loc,
+    };
+    Some(answers(solver, &call, &modes).and_then(|answers| {
+        let occurs = solver.occurs_check();
+        let mut subs = Vec::new();
+        for answer in &answers {
+            subs.extend(unify(e, &solver.copy(answer), occurs)?);
+        }
+        Ok(subs)
+    }))
+}
+
+// the answers in the table of the call `e`, filling it first if needed
+fn answers(solver: &mut Solver, e: &Expr, modes: &[Mode]) -> Result<Vec<Expr>, ApplyError> {
+    let tables = solver.tables();
+    let i = {
+        let t = &mut *tables.borrow_mut();
+        match t.tables.iter().position(|table| variant(&table.call, e)) {
+            Some(i) if t.tables[i].complete => return Ok(t.tables[i].answers.clone()),
+            Some(i) => {
+                // the call is being evaluated further up,
+                // so the calls from there to here depend on it
+                if let Some(d) = t.stack.iter().position(|f| f.table == i) {
+                    let top = t.stack.last_mut().unwrap();
+                    top.low = top.low.min(d);
+                    return Ok(t.tables[i].answers.clone());
+                }
+                i
+            }
+            None => {
+                t.tables.push(Table {
+                    call: e.clone(),
+                    answers: Vec::new(),
+                    complete: false,
+                });
+                t.tables.len() - 1
+            }
+        }
+    };
+    {
+        let t = &mut *tables.borrow_mut();
+        t.stack.push(Frame {
+            table: i,
+            low: t.stack.len(),
+            pending: t.pending.len(),
+        });
+    }
+    let res = fixpoint(solver, &tables, i, e, modes);
+
+    let t = &mut *tables.borrow_mut();
+    let frame = t.stack.pop().unwrap();
+    if let Err(err) = res {
+        t.pending.truncate(frame.pending);
+        return Err(err);
+    }
+    if frame.low == t.stack.len() {
+        // no call further up was used, so this one and the ones that used it are complete
+        for j in t.pending.drain(frame.pending..) {
+            t.tables[j].complete = true;
+        }
+        t.tables[i].complete = true;
+    } else {
+        t.pending.push(i);
+        let parent = t.stack.last_mut().unwrap();
+        parent.low = parent.low.min(frame.low);
+    }
+    Ok(t.tables[i].answers.clone())
+}
+
+// runs the clauses for the call `e` until they give no answer that changes a table
+fn fixpoint(
+    solver: &mut Solver,
+    tables: &RefCell<Tables>,
+    i: usize,
+    e: &Expr,
+    modes: &[Mode],
+) -> Result<(), ApplyError> {
+    loop {
+        let added = tables.borrow().added;
+        for answer in solver.solve_clauses(e)? {
+            let (j, old) = {
+                let t = &mut *tables.borrow_mut();
+                let answers = &mut t.tables[i].answers;
+                match answers.iter().position(|a| same_index(a, &answer, modes)) {
+                    Some(j) => (j, answers[j].clone()),
+                    None => {
+                        answers.push(answer);
+                        t.added += 1;
+                        continue;
+                    }
+                }
+            };
+            // a lattice join runs while the tables are not borrowed
+            let best = best(solver, &old, &answer, modes)?;
+            if !variant(&best, &old) {
+                let t = &mut *tables.borrow_mut();
+                t.tables[i].answers[j] = best;
+                t.added += 1;
+            }
+        }
+        if tables.borrow().added == added {
+            return Ok(());
+        }
+    }
+}
+
+// tells if two answers are the same in their indexed arguments
+fn same_index(a: &Expr, b: &Expr, modes: &[Mode]) -> bool {
+    let (Expr::Fun { args: a, .. }, Expr::Fun { args: b, .. }) = (a, b) else {
+        unreachable!()
+    };
+    a.iter()
+        .zip(b)
+        .zip(modes)
+        .all(|((a, b), mode)| !matches!(mode, Mode::Index) || variant(a, b))
+}
+
+// the answer that is kept out of `old` and `new`, which are the same in their indexed arguments
+fn best(solver: &mut Solver, old: &Expr, new: &Expr, modes: &[Mode]) -> Result<Expr, ApplyError> {
+    let Expr::Fun { name, args, loc } = new else {
+        unreachable!()
+    };
+    let loc = *loc;
+    let Expr::Fun { args: olds, .. } = old else {
+        unreachable!()
+    };
+    let mut best = Vec::new();
+    for ((new, old), mode) in args.iter().zip(olds).zip(modes) {
+        best.push(match mode {
+            Mode::Index => old.clone(),
+            Mode::Min if new.compare(old).is_lt() => new.clone(),
+            Mode::Max if new.compare(old).is_gt() => new.clone(),
+            Mode::Min | Mode::Max => old.clone(),
+            // the old answer stays if the join fails
+            Mode::Lattice(join) => {
+                let joined = solver.var(loc);
+                let goal = Expr::Fun {
+                    name: join.clone(),
+                    args: vec![old.clone(), new.clone(), joined.clone()],
+                    loc,
+                };
+                let mut sols = solver.solve_all(&joined, &goal)?.into_iter();
+                sols.next().unwrap_or_else(|| old.clone())
+            }
+        });
+    }
+    Ok(Expr::Fun {
+        name: name.clone(),
+        args: best,
+        loc,
+    })
+}
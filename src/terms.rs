@@ -0,0 +1,180 @@
+use crate::{
+    builtins::{goal, unify, Subs},
+    expr::{Expr, IdAlloc, CONS, MAX_ARITY, NIL},
+    flags::OccursCheck,
+    unify::{freshen, ApplyError},
+};
+use num_traits::Signed;
+
+// runs the predicates that take terms apart and build them up again.
+// returns `None` for any other goal.
+pub(crate) fn call(
+    e: &Expr,
+    gen: &mut IdAlloc<u64>,
+    occurs: OccursCheck,
+) -> Option<Result<Subs, ApplyError>> {
+    let (name, args) = match e {
+        Expr::Fun { name, args, .. } => (name.as_str(), args.as_slice()),
+        _ => return None,
+    };
+    Some(match (name, args) {
+        ("functor", [t, n, a]) => functor(t, n, a, gen)
+            .and_then(|(t, n, a)| unify(e, &goal(name, vec![t, n, a], e), occurs)),
+        ("arg", [n, t, a]) => arg(n, t).and_then(|arg| match arg {
+            Some(arg) => unify(a, arg, occurs),
+            None => Ok(Vec::new()),
+        }),
+        ("=..", [t, l]) => {
+            univ(t, l).and_then(|(t, l)| unify(e, &goal(name, vec![t, l], e), occurs))
+        }
+        // the copy gets new variables, but the variables that are the same in `t` stay the same
+        ("copy_term", [t, c]) => {
+            gen.new_clause();
+            unify(c, &freshen(t, gen), occurs)
+        }
+        _ => return None,
+    })
+}
+
+// `functor(T, N, A)`: `T` has the name `N` and `A` arguments.
+// if `T` is a variable, it is made into a term with `A` new variables as its arguments.
+// gives the 3 arguments as they have to be.
+fn functor(
+    t: &Expr,
+    n: &Expr,
+    a: &Expr,
+    gen: &mut IdAlloc<u64>,
+) -> Result<(Expr, Expr, Expr), ApplyError> {
+    let loc = t.loc();
+    match t {
+        Expr::Var { .. } => {}
+        Expr::Fun { name, args, .. } => {
+            let arity = Expr::Int {
+                val: args.len() as i64,
+                loc,
+            };
+            return Ok((t.clone(), Expr::atom(name, loc), arity));
+        }
+        // numbers and strings are their own name
+        _ => return Ok((t.clone(), t.clone(), Expr::Int { val: 0, loc })),
+    }
+    let arity = match a {
+        Expr::Var { .. } => return Err(ApplyError::Instantiation),
+        Expr::Int { val, .. } if *val > MAX_ARITY as i64 => {
+            return Err(ApplyError::Representation("max_arity"))
+        }
+        Expr::Int { val, .. } if *val >= 0 => *val as usize,
+        Expr::Int { .. } => return Err(ApplyError::Domain("not_less_than_zero", a.clone())),
+        Expr::BigInt { val, .. } if val.is_positive() => {
+            return Err(ApplyError::Representation("max_arity"))
+        }
+        Expr::BigInt { .. } => return Err(ApplyError::Domain("not_less_than_zero", a.clone())),
+        _ => return Err(ApplyError::Type("integer", a.clone())),
+    };
+    let t = match n {
+        Expr::Var { .. } => return Err(ApplyError::Instantiation),
+        Expr::Fun { args, .. } if !args.is_empty() => {
+            return Err(ApplyError::Type("atomic", n.clone()))
+        }
+        _ if arity == 0 => n.clone(),
+        Expr::Fun { name, .. } => Expr::Fun {
+            name: name.clone(),
+            args: (0..arity)
+                .map(|_| {
+                    let id = gen.fresh();
+                    Expr::Var {
+                        name: format!("_G{}", id),
+                        id,
+                        loc,
+                    }
+                })
+                .collect(),
+            loc,
+        },
+        _ => return Err(ApplyError::Type("atom", n.clone())),
+    };
+    Ok((t, n.clone(), a.clone()))
+}
+
+// `arg(N, T, A)`: the `N`th argument of `T`, counting from 1, if there is one
+fn arg<'a>(n: &Expr, t: &'a Expr) -> Result<Option<&'a Expr>, ApplyError> {
+    let n = match n {
+        Expr::Var { .. } => return Err(ApplyError::Instantiation),
+        Expr::Int { val, .. } if *val >= 0 => *val as usize,
+        Expr::Int { .. } => return Err(ApplyError::Domain("not_less_than_zero", n.clone())),
+        Expr::BigInt { .. } => return Ok(None),
+        _ => return Err(ApplyError::Type("integer", n.clone())),
+    };
+    match t {
+        Expr::Var { .. } => Err(ApplyError::Instantiation),
+        Expr::Fun { args, .. } if !args.is_empty() => {
+            Ok(n.checked_sub(1).and_then(|i| args.get(i)))
+        }
+        _ => Err(ApplyError::Type("compound", t.clone())),
+    }
+}
+
+// `T =.. L`: `L` is the list of the name of `T` and its arguments,
+// like `f(a, b) =.. [f, a, b]`. gives both sides as they have to be.
+fn univ(t: &Expr, l: &Expr) -> Result<(Expr, Expr), ApplyError> {
+    let loc = t.loc();
+    let nil = Expr::atom(NIL, loc);
+    match t {
+        Expr::Var { .. } => {}
+        Expr::Fun { name, args, .. } => {
+            let name = Expr::atom(name, loc);
+            let items = std::iter::once(name).chain(args.iter().cloned()).collect();
+            return Ok((t.clone(), Expr::list(items, nil)));
+        }
+        _ => return Ok((t.clone(), Expr::list(vec![t.clone()], nil))),
+    }
+
+    let mut items = list(l)?.into_iter();
+    let Some(head) = items.next() else {
+        return Err(ApplyError::Domain("non_empty_list", l.clone()));
+    };
+    let t = match head {
+        Expr::Var { .. } => return Err(ApplyError::Instantiation),
+        Expr::Fun { args, .. } if !args.is_empty() => {
+            return Err(ApplyError::Type("atomic", head.clone()))
+        }
+        _ if items.len() == 0 => head.clone(),
+        Expr::Fun { name, .. } => Expr::Fun {
+            name: name.clone(),
+            args: items.cloned().collect(),
+            loc,
+        },
+        _ => return Err(ApplyError::Type("atom", head.clone())),
+    };
+    Ok((t, l.clone()))
+}
+
+// tells if `l` is a list or a partial list like `[a | T]`, which can still become one
+pub(crate) fn is_partial_list(l: &Expr) -> bool {
+    let mut tail = l;
+    loop {
+        match tail {
+            Expr::Var { .. } => return true,
+            Expr::Fun { name, args, .. } if name == NIL && args.is_empty() => return true,
+            Expr::Fun { name, args, .. } if name == CONS && args.len() == 2 => tail = &args[1],
+            _ => return false,
+        }
+    }
+}
+
+// the items of a list, which has to be a proper list
+pub(crate) fn list(l: &Expr) -> Result<Vec<&Expr>, ApplyError> {
+    let mut items = Vec::new();
+    let mut tail = l;
+    loop {
+        match tail {
+            Expr::Var { .. } => return Err(ApplyError::Instantiation),
+            Expr::Fun { name, args, .. } if name == NIL && args.is_empty() => return Ok(items),
+            Expr::Fun { name, args, .. } if name == CONS && args.len() == 2 => {
+                items.push(&args[0]);
+                tail = &args[1];
+            }
+            _ => return Err(ApplyError::Type("list", l.clone())),
+        }
+    }
+}
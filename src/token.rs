@@ -1,7 +1,8 @@
 use core::fmt;
-use std::fmt::Display;
+use std::{fmt::Display, iter::Peekable, str::CharIndices};
 
 use crate::error::{Error, ErrorTy, Loc};
+use num_bigint::BigInt;
 
 pub struct Scanner<'a> {
     loc: Loc,
@@ -13,22 +14,40 @@ pub struct Scanner<'a> {
 pub struct Token {
     ty: TokenTy,
     loc: Loc,
+    // whether there was whitespace or a comment right before the token
+    layout: bool,
 }
 
 impl Token {
     pub fn ty(self) -> TokenTy {
         self.ty
     }
+    pub fn loc(&self) -> Loc {
+        self.loc
+    }
+    pub fn layout(&self) -> bool {
+        self.layout
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TokenTy {
     Ident(String),
+    // a quoted atom like `'hello world'`, which is never a variable
+    Quoted(String),
+    // a double-quoted text like `"hello world"`
+    Str(String),
+    Int(i64),
+    BigInt(BigInt),
+    Float(f64),
     Lparen,
     Rparen,
-    Pen, // is
+    Lbracket,
+    Rbracket,
+    Lbrace,
+    Rbrace,
+    Bar,
     Period,
-    Colon,
     Comma,
     Eof,
 }
@@ -39,12 +58,20 @@ impl Display for TokenTy {
 
         let name = match self {
             Ident(s) => format!("identifier `{}`", s),
+            Quoted(s) => format!("quoted atom `{}`", s),
+            Str(s) => format!("string `{}`", s),
+            Int(n) => format!("integer `{}`", n),
+            BigInt(n) => format!("integer `{}`", n),
+            Float(n) => format!("float `{}`", n),
             x => match x {
-                Pen => "token `:-`",
                 Lparen => "token `(`",
                 Rparen => "token `)`",
+                Lbracket => "token `[`",
+                Rbracket => "token `]`",
+                Lbrace => "token `{`",
+                Rbrace => "token `}`",
+                Bar => "token `|`",
                 Period => "token `.`",
-                Colon => "token `:`",
                 Comma => "token `,`",
                 Eof => "EOF",
                 _ => unreachable!(),
@@ -64,15 +91,6 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    pub fn expect_identifier(&mut self) -> Result<(Loc, String), Error> {
-        let res = self.next_token()?;
-        if let TokenTy::Ident(x) = res.ty {
-            Ok((res.loc, x))
-        } else {
-            self.syntax_err(res.loc, format!("expected idenifier, found {}", res.ty))
-        }
-    }
-
     pub fn expect_token(&mut self, token: TokenTy) -> Result<Token, Error> {
         let res = self.next_token()?;
         if res.ty != token {
@@ -110,42 +128,61 @@ impl<'a> Scanner<'a> {
     }
 
     fn next_token_internal(&mut self) -> Result<Token, Error> {
-        self.skip_whitespace();
+        let layout = self.skip_whitespace();
+        let loc = self.loc();
+        let ty = self.token_ty()?;
+        Ok(Token { ty, loc, layout })
+    }
+
+    fn token_ty(&mut self) -> Result<TokenTy, Error> {
+        use TokenTy::*;
 
         if self.rest.is_empty() {
-            return Ok(Token {
-                loc: self.loc(),
-                ty: TokenTy::Eof,
-            });
+            return Ok(Eof);
         }
         let mut iter = self.rest.char_indices();
         let (_, c) = iter.next().unwrap();
 
-        if is_break(c) {
-            use TokenTy::*;
-
-            let ret = Ok(Token {
-                loc: self.loc(),
-                ty: match c {
-                    ':' => match iter.next() {
-                        Some((_, '-')) => {
-                            self.skip(1);
-                            Pen
-                        }
-                        _ => Colon,
-                    },
-                    ',' => Comma,
-                    '.' => Period,
-                    '(' => Lparen,
-                    ')' => Rparen,
-                    _ => {
-                        return self.syntax_err(self.loc(), format!("unrecognized character {}", c))
-                    }
-                },
-            });
+        if is_symbol(c) {
+            // symbolic atoms such as `+` or `=<` are runs of symbol characters
+            let mut i = self.rest.len();
+            for (j, c) in iter {
+                if !is_symbol(c) {
+                    i = j;
+                    break;
+                }
+            }
+            // a `.` followed by whitespace, a comment or the end of the input ends the clause
+            let end = !self.rest[i..].starts_with(|c: char| !c.is_whitespace() && c != '%');
+            if &self.rest[..i] == "." && end {
+                self.skip(i);
+                return Ok(Period);
+            }
+            Ok(self.ident(i))
+        } else if c == '\'' || c == '"' {
+            let loc = self.loc();
+            self.skip(1);
+            let text = self.quoted(c, loc)?;
+            Ok(if c == '"' { Str(text) } else { Quoted(text) })
+        } else if is_break(c) {
+            let ty = match c {
+                ',' => Comma,
+                '(' => Lparen,
+                ')' => Rparen,
+                '[' => Lbracket,
+                ']' => Rbracket,
+                '{' => Lbrace,
+                '}' => Rbrace,
+                '|' => Bar,
+                // solo atoms
+                '!' | ';' => Ident(c.to_string()),
+                _ => return self.syntax_err(self.loc(), format!("unrecognized character {}", c)),
+            };
             self.skip(c.len_utf8());
-            ret
-        } else if c.is_alphabetic() {
+            Ok(ty)
+        } else if c.is_ascii_digit() {
+            self.number()
+        } else if c.is_alphabetic() || c == '_' {
             let mut i = self.rest.len();
             for (j, c) in iter {
                 if is_break(c) {
@@ -153,15 +190,128 @@ impl<'a> Scanner<'a> {
                     break;
                 }
             }
-            Ok(Token {
-                loc: self.loc(),
-                ty: self.ident(i),
-            })
+            Ok(self.ident(i))
         } else {
             self.syntax_err(self.loc(), format!("unrecognized character {}", c))
         }
     }
 
+    // lexes a number literal. these are either integers, such as
+    // `42`, `0x2a`, `0o52`, `0b101010` or `0'a`, or floats, such as `4.2` or `4.2e1`.
+    // a float followed by `Inf` or `NaN` is infinite or not a number, as in `1.0Inf` or `1.5NaN`
+    fn number(&mut self) -> Result<TokenTy, Error> {
+        let loc = self.loc();
+        let mut next = self.rest[1..].chars();
+        let (radix, prefix) = match (self.rest.as_bytes()[0], next.next(), next.next()) {
+            (b'0', Some('x'), Some(c)) if c.is_ascii_hexdigit() => (16, 2),
+            (b'0', Some('o'), Some(c)) if c.is_digit(8) => (8, 2),
+            (b'0', Some('b'), Some(c)) if c.is_digit(2) => (2, 2),
+            (b'0', Some('\''), Some(_)) => return self.char_code(loc),
+            _ => (10, 0),
+        };
+        let len = self.rest[prefix..]
+            .char_indices()
+            .find(|(_i, c)| !c.is_digit(radix))
+            .map(|(i, _c)| prefix + i)
+            .unwrap_or(self.rest.len());
+        if prefix == 0 {
+            if let Some(len) = self.fraction(len) {
+                let mut val = self.rest[..len].parse().unwrap();
+                let special = self.rest[len..]
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .map_or(&self.rest[len..], |i| &self.rest[len..len + i]);
+                let len = match special {
+                    "Inf" => {
+                        val = f64::INFINITY;
+                        len + 3
+                    }
+                    "NaN" => {
+                        val = f64::NAN;
+                        len + 3
+                    }
+                    _ => len,
+                };
+                self.skip(len);
+                return Ok(TokenTy::Float(val));
+            }
+        }
+        let digits = &self.rest[prefix..len];
+        let ty = match i64::from_str_radix(digits, radix) {
+            Ok(val) => TokenTy::Int(val),
+            Err(_) => TokenTy::BigInt(BigInt::parse_bytes(digits.as_bytes(), radix).unwrap()),
+        };
+        self.skip(len);
+        Ok(ty)
+    }
+
+    // lexes a character code such as `0'a`, `0'''` or `0'\n`
+    fn char_code(&mut self, loc: Loc) -> Result<TokenTy, Error> {
+        let mut chars = self.rest[2..].char_indices().peekable();
+        let c = match chars.next().unwrap().1 {
+            '\\' => match escape(&mut chars) {
+                Some(Some(c)) => c,
+                _ => return self.syntax_err(loc, "undefined escape sequence".to_string()),
+            },
+            // the quote itself has to be doubled
+            '\'' if chars.next_if(|(_i, c)| *c == '\'').is_none() => {
+                return self.syntax_err(loc, "expected `'` after `0''`".to_string())
+            }
+            c => c,
+        };
+        let len = 2 + chars.peek().map_or(self.rest.len() - 2, |(i, _c)| *i);
+        self.skip(len);
+        Ok(TokenTy::Int(c as i64))
+    }
+
+    // lexes the text between two `q` quotes, with `self.rest` starting right after the first one.
+    // the quote can appear inside if it is doubled, as in `'don''t'`, or escaped.
+    fn quoted(&mut self, q: char, loc: Loc) -> Result<String, Error> {
+        let mut s = String::new();
+        let mut chars = self.rest.char_indices().peekable();
+        loop {
+            match chars.next() {
+                Some((i, c)) if c == q => {
+                    if chars.next_if(|(_i, c)| *c == q).is_some() {
+                        s.push(q);
+                        continue;
+                    }
+                    self.skip(i + 1);
+                    break Ok(s);
+                }
+                Some((_, '\\')) => match escape(&mut chars) {
+                    Some(Some(c)) => s.push(c),
+                    Some(None) => {}
+                    None => break self.syntax_err(loc, "undefined escape sequence".to_string()),
+                },
+                Some((_, c)) => s.push(c),
+                None => break self.syntax_err(loc, format!("unterminated quoted text {}", q)),
+            }
+        }
+    }
+
+    // if the integer part ending at `i` is followed by a fraction (and maybe an exponent),
+    // gives the length of the whole float literal.
+    // a `.` that is not followed by a digit ends the clause instead, like in `X is 3.`
+    fn fraction(&self, i: usize) -> Option<usize> {
+        let digits = |i: usize| {
+            self.rest[i..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(self.rest.len(), |j| i + j)
+        };
+        let b = self.rest.as_bytes();
+        if b.get(i) != Some(&b'.') || !b.get(i + 1).is_some_and(u8::is_ascii_digit) {
+            return None;
+        }
+        let mut len = digits(i + 1);
+        if let Some(b'e' | b'E') = b.get(len) {
+            let sign = matches!(b.get(len + 1), Some(b'+' | b'-')) as usize;
+            if b.get(len + 1 + sign).is_some_and(u8::is_ascii_digit) {
+                len = digits(len + 1 + sign);
+            }
+        }
+        Some(len)
+    }
+
     pub(crate) fn syntax_err<T>(&self, loc: Loc, desc: String) -> Result<T, Error> {
         self.error(loc, ErrorTy::SyntaxError, desc)
     }
@@ -177,8 +327,9 @@ impl<'a> Scanner<'a> {
         Ident(ident.to_string())
     }
 
-    // skip whitespaces and comments
-    fn skip_whitespace(&mut self) {
+    // skip whitespaces and comments, telling if there were any
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.rest.len();
         loop {
             let i = self
                 .rest
@@ -188,7 +339,7 @@ impl<'a> Scanner<'a> {
                 .unwrap_or(self.rest.len());
             self.skip(i);
             if !self.rest.starts_with('%') {
-                break;
+                break self.rest.len() != start;
             }
             let i = self
                 .rest
@@ -211,6 +362,40 @@ impl<'a> Scanner<'a> {
     }
 }
 
+// reads an escape sequence right after a `\\`.
+// gives `Some(None)` for a `\\` at the end of a line, which continues the text on the next one.
+fn escape(chars: &mut Peekable<CharIndices>) -> Option<Option<char>> {
+    let c = match chars.next()?.1 {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'a' => '\x07',
+        'b' => '\x08',
+        'f' => '\x0c',
+        'v' => '\x0b',
+        'e' => '\x1b',
+        's' => ' ',
+        '\n' => return Some(None),
+        c @ ('\\' | '\'' | '"' | '`') => c,
+        // numeric escapes, such as `\x41\` or `\101\`
+        c @ ('x' | '0'..='7') => {
+            let radix = if c == 'x' { 16 } else { 8 };
+            let mut code = c.to_digit(8).unwrap_or(0);
+            while let Some((_, d)) = chars.next_if(|(_i, d)| d.is_digit(radix)) {
+                code = code.checked_mul(radix)?.checked_add(d.to_digit(radix)?)?;
+            }
+            chars.next_if(|(_i, c)| *c == '\\')?;
+            char::from_u32(code)?
+        }
+        _ => return None,
+    };
+    Some(Some(c))
+}
+
+pub(crate) fn is_symbol(c: char) -> bool {
+    "+-*/\\^<>=~:.?@#&$".contains(c)
+}
+
 fn is_break(c: char) -> bool {
     !c.is_alphanumeric() && c != '_'
 }
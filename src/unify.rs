@@ -1,35 +1,165 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
 
 use crate::{
     expr::{Expr, IdAlloc},
-    Def,
+    flags::OccursCheck,
+    parser::Def,
 };
 
 #[derive(Debug)]
 pub(crate) enum ApplyError {
     UnifyFail,
-    Undef,
     NoMatch,
+    // an argument was not instantiated enough, like the `X` in `Y is X + 1`
+    Instantiation,
+    // an argument had the wrong type, e.g. `foo` is not `evaluable`
+    Type(&'static str, Expr),
+    // an argument had the right type but an invalid value,
+    // e.g. `foo` is not a `prolog_flag`
+    Domain(&'static str, Expr),
+    // an action that is not allowed, e.g. to `modify` the `operator` `','`
+    Permission(&'static str, &'static str, Expr),
+    // a value can't be represented, e.g. a `character_code` that is not a character
+    Representation(&'static str),
+    // a text could not be read, e.g. an `illegal_number`
+    Syntax(&'static str),
+    // arithmetic went wrong, e.g. `zero_divisor`
+    Evaluation(&'static str),
+    // a variable would be bound to a term that contains it,
+    // with the `occurs_check` flag set to `error`
+    OccursCheck(Box<(Expr, Expr)>),
+    // a ball thrown with `throw/1`
+    Thrown(Expr),
 }
 
-impl Def {
-    pub(crate) fn apply(&self, e: &Expr) -> Result<HashMap<u64, Expr>, ApplyError> {
-        let mut bindings = HashMap::new();
-        unify(&mut bindings, &self.pat, e)?;
-        Ok(bindings.into_iter().map(|(k, v)| (k, v.clone())).collect())
+impl Display for ApplyError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ApplyError::*;
+        match self {
+            UnifyFail => write!(fmt, "unification failure"),
+            NoMatch => write!(fmt, "unknown procedure"),
+            Instantiation => write!(fmt, "arguments are not sufficiently instantiated"),
+            Type(ty, culprit) => write!(fmt, "type error: expected {}, found {}", ty, culprit),
+            Domain(d, culprit) => write!(fmt, "domain error: expected {}, found {}", d, culprit),
+            Permission(action, ty, culprit) => {
+                write!(
+                    fmt,
+                    "permission error: cannot {} {} {}",
+                    action, ty, culprit
+                )
+            }
+            Representation(r) => write!(fmt, "representation error: {}", r),
+            Syntax(s) => write!(fmt, "syntax error: {}", s),
+            Evaluation(e) => write!(fmt, "evaluation error: {}", e),
+            OccursCheck(b) => write!(fmt, "occurs check: {} occurs in {}", b.0, b.1),
+            Thrown(ball) => write!(fmt, "uncaught exception: {}", ball),
+        }
     }
 }
 
-// try to unify 2 expressions
-fn unify<'a>(b: &mut HashMap<u64, &'a Expr>, pat: &'a Expr, e: &'a Expr) -> Result<(), ApplyError> {
-    match (pat, e) {
-        (Expr::Var { id, .. }, Expr::Var { id: id2, .. }) if b.contains_key(id2) => {
-            solve(b, *id, b[id2])
-        }
-        (Expr::Var { id, .. }, _) => solve(b, *id, e),
+impl ApplyError {
+    // the ball that is thrown for the error, which happened while solving `goal`.
+    // errors are thrown as the ISO `error(Formal, Context)` terms,
+    // with the predicate indicator of `goal` as the context.
+    pub(crate) fn into_ball(self, goal: &Expr) -> Expr {
+        use ApplyError::*;
+        let loc = goal.loc();
+        let fun = |name: &str, args: Vec<Expr>| Expr::Fun {
+            name: name.to_string(),
+            args,
+            loc,
+        };
+        let atom = |name: &str| Expr::atom(name, loc);
+        let pi = match goal {
+            Expr::Fun { name, args, .. } => fun(
+                "/",
+                vec![
+                    atom(name),
+                    Expr::Int {
+                        val: args.len() as i64,
+                        loc,
+                    },
+                ],
+            ),
+            _ => goal.clone(),
+        };
+        let formal = match self {
+            Thrown(ball) => return ball,
+            // the solver takes this as a failure, not as an error
+            UnifyFail => unreachable!(),
+            NoMatch => fun("existence_error", vec![atom("procedure"), pi.clone()]),
+            Instantiation => atom("instantiation_error"),
+            Type(ty, culprit) => fun("type_error", vec![atom(ty), culprit]),
+            Domain(d, culprit) => fun("domain_error", vec![atom(d), culprit]),
+            Permission(action, ty, culprit) => {
+                fun("permission_error", vec![atom(action), atom(ty), culprit])
+            }
+            Representation(r) => fun("representation_error", vec![atom(r)]),
+            Syntax(s) => fun("syntax_error", vec![atom(s)]),
+            Evaluation(e) => fun("evaluation_error", vec![atom(e)]),
+            OccursCheck(b) => fun("occurs_check", vec![b.0, b.1]),
+        };
+        fun("error", vec![formal, pi])
+    }
+}
 
-        (_, Expr::Var { id, .. }) => solve(b, *id, pat),
+impl Def {
+    // unifies a fresh copy of the clause with the goal `e`,
+    // giving back the bindings and the body of that copy
+    pub(crate) fn apply(
+        &self,
+        e: &Expr,
+        gen: &mut IdAlloc<u64>,
+        occurs: OccursCheck,
+    ) -> Result<(HashMap<u64, Expr>, Vec<Expr>), ApplyError> {
+        gen.new_clause();
+        let sub = unify_exprs(&freshen(&self.pat, gen), e, occurs)?;
+        Ok((sub, self.rep.iter().map(|e| freshen(e, gen)).collect()))
+    }
+}
+
+// unifies 2 expressions, giving back the bindings that make them equal.
+// `occurs` tells what to do when a variable would be bound to a term that contains it.
+pub(crate) fn unify_exprs(
+    a: &Expr,
+    e: &Expr,
+    occurs: OccursCheck,
+) -> Result<HashMap<u64, Expr>, ApplyError> {
+    let mut bindings = HashMap::new();
+    unify(&mut bindings, a, e, occurs, &mut HashSet::new())?;
+    Ok(bindings.into_iter().map(|(k, v)| (k, v.clone())).collect())
+}
 
+// try to unify 2 expressions.
+// functors only unify if both their names and their arities are the same,
+// so `f(a)` does not unify with `f(a, b)`.
+// `seen` has the pairs of compound terms that were already unified. they are taken to unify
+// when they come up again, which happens when a variable is bound to a term that contains it.
+fn unify<'a>(
+    b: &mut HashMap<u64, &'a Expr>,
+    pat: &'a Expr,
+    e: &'a Expr,
+    occurs: OccursCheck,
+    seen: &mut HashSet<(*const Expr, *const Expr)>,
+) -> Result<(), ApplyError> {
+    let (pat, e) = (walk(b, pat), walk(b, e));
+    crate::with_stacker(|| match (pat, e) {
+        (Expr::Var { id, .. }, Expr::Var { id: id2, .. }) if id == id2 => Ok(()),
+        (var @ Expr::Var { id, .. }, e) | (e, var @ Expr::Var { id, .. }) => {
+            if occurs != OccursCheck::False && occurs_in(b, *id, e) {
+                return match occurs {
+                    OccursCheck::Error => {
+                        Err(ApplyError::OccursCheck(Box::new((var.clone(), e.clone()))))
+                    }
+                    _ => Err(ApplyError::UnifyFail),
+                };
+            }
+            b.insert(*id, e);
+            Ok(())
+        }
         (
             Expr::Fun { name, args, .. },
             Expr::Fun {
@@ -37,86 +167,122 @@ fn unify<'a>(b: &mut HashMap<u64, &'a Expr>, pat: &'a Expr, e: &'a Expr) -> Resu
                 args: args2,
                 ..
             },
-        ) if name == name2 => {
+        ) if name == name2 && args.len() == args2.len() => {
+            if !seen.insert((pat, e)) {
+                return Ok(());
+            }
             for (arg1, arg2) in args.iter().zip(args2.iter()) {
-                unify(b, arg1, arg2)?;
+                unify(b, arg1, arg2, occurs, seen)?;
             }
             Ok(())
         }
+        (Expr::Int { val, .. }, Expr::Int { val: val2, .. }) if val == val2 => Ok(()),
+        (Expr::BigInt { val, .. }, Expr::BigInt { val: val2, .. }) if val == val2 => Ok(()),
+        (Expr::Float { val, .. }, Expr::Float { val: val2, .. }) if val == val2 => Ok(()),
+        (Expr::Str { val, .. }, Expr::Str { val: val2, .. }) if val == val2 => Ok(()),
         _ => Err(ApplyError::UnifyFail),
+    })
+}
+
+// tells if the variable `id` occurs in `e`, following the bindings
+fn occurs_in(b: &HashMap<u64, &Expr>, id: u64, e: &Expr) -> bool {
+    crate::with_stacker(|| match walk(b, e) {
+        Expr::Var { id: id2, .. } => *id2 == id,
+        Expr::Fun { args, .. } => args.iter().any(|arg| occurs_in(b, id, arg)),
+        _ => false,
+    })
+}
+
+// tells if two expressions are the same up to the names of their variables,
+// like `f(X, Y, X)` and `f(A, B, A)`
+pub(crate) fn variant(a: &Expr, b: &Expr) -> bool {
+    fn go(m: &mut HashMap<u64, u64>, rev: &mut HashMap<u64, u64>, a: &Expr, b: &Expr) -> bool {
+        crate::with_stacker(|| match (a, b) {
+            (Expr::Var { id, .. }, Expr::Var { id: id2, .. }) => {
+                *m.entry(*id).or_insert(*id2) == *id2 && *rev.entry(*id2).or_insert(*id) == *id
+            }
+            (
+                Expr::Fun { name, args, .. },
+                Expr::Fun {
+                    name: name2,
+                    args: args2,
+                    ..
+                },
+            ) => {
+                name == name2
+                    && args.len() == args2.len()
+                    && args.iter().zip(args2).all(|(a, b)| go(m, rev, a, b))
+            }
+            (Expr::Var { .. }, _) | (_, Expr::Var { .. }) => false,
+            (a, b) => a == b,
+        })
     }
+    go(&mut HashMap::new(), &mut HashMap::new(), a, b)
+}
+
+// follows the bindings of a variable until it reaches
+// either a non-variable or a variable that is still unbound.
+// for example, with the bindings X -> Y, Y -> a, walking X gives `a`.
+fn walk<'a>(b: &HashMap<u64, &'a Expr>, mut e: &'a Expr) -> &'a Expr {
+    while let Expr::Var { id, .. } = e {
+        match b.get(id) {
+            Some(x) => e = x,
+            None => break,
+        }
+    }
+    e
 }
 
 // replace all the variables by their replacement given in bindings
-// and freshen up the younglings
-pub(crate) fn substitute_and_freshen(
-    gen: &mut IdAlloc<u64>,
-    b: &HashMap<u64, Expr>,
-    rep: &Expr,
-) -> Expr {
-    match rep {
-        Expr::Var { id, .. } => freshen(b.get(&id).unwrap_or(rep), gen),
-        Expr::Fun { name, args, loc } => Expr::Fun {
-            name: name.clone(),
-            loc: *loc,
-            args: args
-                .iter()
-                .map(|i| substitute_and_freshen(gen, b, i))
-                .collect(),
-        },
+pub(crate) fn substitute(b: &HashMap<u64, Expr>, rep: &Expr) -> Expr {
+    // `expanding` has the variables whose bindings are being replaced,
+    // which are left as they are when they come up again, so that a binding
+    // like X -> f(X), which is made without the occurs check, does not loop
+    fn go(b: &HashMap<u64, Expr>, rep: &Expr, expanding: &mut Vec<u64>) -> Expr {
+        crate::with_stacker(|| match rep {
+            // the binding itself may contain variables that are bound too
+            Expr::Var { id, .. } if !expanding.contains(id) => match b.get(id) {
+                Some(e) => {
+                    expanding.push(*id);
+                    let e = go(b, e, expanding);
+                    expanding.pop();
+                    e
+                }
+                None => rep.clone(),
+            },
+            Expr::Fun { name, args, loc } => Expr::Fun {
+                name: name.clone(),
+                loc: *loc,
+                args: args.iter().map(|i| go(b, i, expanding)).collect(),
+            },
+            Expr::Var { .. }
+            | Expr::Int { .. }
+            | Expr::BigInt { .. }
+            | Expr::Float { .. }
+            | Expr::Str { .. } => rep.clone(),
+        })
     }
+    go(b, rep, &mut Vec::new())
 }
 
 // freshens up an expression by giving it coffee
-// (or more accurately, giving the variables new ids)
-fn freshen(e: &Expr, gen: &mut IdAlloc<u64>) -> Expr {
-    match e {
-        Expr::Var { name, id, loc } => Expr::Var {
-            name: name.clone(),
-            id: gen.alloc(*id),
-            loc: *loc,
-        },
+// (or more accurately, giving the variables new ids).
+// the same variable gets the same new id as long as `gen` is on the same clause.
+pub(crate) fn freshen(e: &Expr, gen: &mut IdAlloc<u64>) -> Expr {
+    crate::with_stacker(|| match e {
+        Expr::Var { id, loc, .. } => {
+            let id = gen.alloc(*id);
+            Expr::Var {
+                name: format!("_G{}", id),
+                id,
+                loc: *loc,
+            }
+        }
         Expr::Fun { name, args, loc } => Expr::Fun {
             name: name.clone(),
             args: args.iter().map(|i| freshen(i, gen)).collect(),
             loc: *loc,
         },
-    }
-}
-
-// solves the problem of unifying one variable that has already been unified
-// for example, the second X in
-//     eq(X, X).
-// when we need to unify an expression, such as `eq(Z, Y)`
-// by the time we get to unify X and Y, we already have the binding x -> Z in our map,
-// so we cannot just insert X -> Z.
-//
-// how does this function do it?
-// =============================
-// this function just tries different combinations and sees if they work.
-fn solve<'a>(b: &mut HashMap<u64, &'a Expr>, id: u64, e: &'a Expr) -> Result<(), ApplyError> {
-    if let Some(e2) = b.get(&id).copied() {
-        let res = if e == e2 {
-            Ok(())
-        } else if let Expr::Var { id, .. } = e {
-            solve(b, *id, e2)
-        } else {
-            Err(ApplyError::UnifyFail)
-        };
-        if res.is_ok() {
-            return res;
-        }
-        if let Expr::Var { id: id2, .. } = e2 {
-            let res2 = solve(b, *id2, e);
-            if res2.is_ok() {
-                *b.get_mut(&id).unwrap() = e;
-            }
-            res2
-        } else {
-            Err(ApplyError::UnifyFail)
-        }
-    } else {
-        b.insert(id, e);
-        Ok(())
-    }
+        Expr::Int { .. } | Expr::BigInt { .. } | Expr::Float { .. } | Expr::Str { .. } => e.clone(),
+    })
 }